@@ -1,6 +1,6 @@
 use bevy::{app::ScheduleRunnerPlugin, prelude::*};
 
-use bevy_histrion_packer::HistrionPackerPlugin;
+use bevy_histrion_packer::{HistrionPackerMode, HistrionPackerPlugin};
 use text_asset::{TextAsset, TextAssetLoader};
 
 fn main() {
@@ -9,10 +9,10 @@ fn main() {
             DefaultPlugins
                 .build()
                 .set(ScheduleRunnerPlugin::run_once())
-                .add_before::<AssetPlugin>(HistrionPackerPlugin {
-                    source: env!("CARGO_MANIFEST_DIR").to_string() + "/assets.hpak",
-                    mode: bevy_histrion_packer::HistrionPackerMode::ReplaceDefaultProcessed,
-                })
+                .add_before::<AssetPlugin>(
+                    HistrionPackerPlugin::new(concat!(env!("CARGO_MANIFEST_DIR"), "/assets.hpak"))
+                        .with_mode(HistrionPackerMode::ReplaceDefaultProcessed),
+                )
                 .set(AssetPlugin {
                     mode: AssetMode::Processed,
                     ..default()
@@ -0,0 +1,379 @@
+//! Command-line tool to create and inspect HPAK archives.
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Component, Path, PathBuf},
+    process::ExitCode,
+};
+
+use bevy_histrion_packer::{
+    CompressionMethod, HpakReader,
+    writer::{HpakWriter, ZlibEncoder, migrate_archive, set_default_extension_compression_methods},
+};
+use clap::{Parser, Subcommand};
+use futures_lite::io::BlockOn;
+
+#[derive(Parser)]
+#[command(name = "hpak", version, about = "Create and inspect HPAK archives")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Pack a directory into an archive.
+    ///
+    /// Every file of the directory is added along with its `.meta` file, if any.
+    Pack {
+        /// Directory to pack.
+        input: PathBuf,
+        /// Path of the archive to create.
+        #[arg(short, long, default_value = "assets.hpak")]
+        output: PathBuf,
+        /// Data compression method for files without an extension override
+        /// (none, zlib, zstd, zstd:<level> or lz4).
+        #[arg(short, long, value_parser = parse_compression_method)]
+        compression: Option<CompressionMethod>,
+        /// Compression method of the metadata.
+        #[arg(short, long, value_parser = parse_compression_method)]
+        meta_compression: Option<CompressionMethod>,
+        /// Encoder used for zlib compression (flate2:<level> or zopfli:<iterations>).
+        #[arg(short, long, value_parser = parse_zlib_encoder)]
+        zlib: Option<ZlibEncoder>,
+        /// Compression method for an extension, e.g. `png=none` (repeatable).
+        #[arg(short, long = "extension", value_parser = parse_extension)]
+        extensions: Vec<(String, CompressionMethod)>,
+        /// Don't use the built-in compression methods per extension.
+        #[arg(long)]
+        no_default_extensions: bool,
+        /// Alignment of each entry in bytes, must be a power of 2 (0 disables alignment).
+        #[arg(short, long)]
+        alignment: Option<u64>,
+        /// Split compressed data into blocks of this many uncompressed bytes (0 disables blocks).
+        #[arg(short, long)]
+        block_size: Option<u64>,
+        /// Store RON metadata as is instead of minifying it.
+        #[arg(long)]
+        no_minify_metadata: bool,
+        /// Previous version of the archive, unchanged entries are copied from it instead
+        /// of being compressed again. Must not be the output archive.
+        #[arg(short, long)]
+        previous: Option<PathBuf>,
+        /// Number of threads compressing entries, defaults to the available parallelism.
+        #[arg(short = 'j', long)]
+        threads: Option<usize>,
+        /// Directory of the base assets, only the files that changed since are packed and
+        /// the deleted ones are recorded, making a patch archive to mount above the base one.
+        #[arg(long)]
+        base: Option<PathBuf>,
+    },
+    /// List the files of an archive.
+    List {
+        /// Archive to inspect.
+        archive: PathBuf,
+    },
+    /// Extract files from an archive along with their `.meta` file, if any.
+    Extract {
+        /// Archive to extract from.
+        archive: PathBuf,
+        /// Paths of the files to extract, every file is extracted if empty.
+        paths: Vec<PathBuf>,
+        /// Directory to extract the files into.
+        #[arg(short, long, default_value = ".")]
+        output: PathBuf,
+    },
+    /// Print information about an archive.
+    Info {
+        /// Archive to inspect.
+        archive: PathBuf,
+    },
+    /// Rewrite an archive written with a previous format version to the current one.
+    Migrate {
+        /// Archive to migrate.
+        archive: PathBuf,
+        /// Path of the migrated archive, must not be the archive to migrate.
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    let result = match cli.command {
+        Command::Pack {
+            input,
+            output,
+            compression,
+            meta_compression,
+            zlib,
+            extensions,
+            no_default_extensions,
+            alignment,
+            block_size,
+            no_minify_metadata,
+            previous,
+            threads,
+            base,
+        } => {
+            let mut writer = match HpakWriter::new(&output) {
+                Ok(writer) => writer,
+                Err(err) => return fail(err),
+            };
+
+            if !no_default_extensions {
+                set_default_extension_compression_methods(&mut writer);
+            }
+
+            for (extension, method) in extensions {
+                writer.default_compression_for_extension(&extension, method);
+            }
+
+            if let Some(method) = compression {
+                writer.default_data_compression(method);
+            }
+
+            if let Some(method) = meta_compression {
+                writer.meta_compression(method);
+            }
+
+            if let Some(encoder) = zlib {
+                writer.zlib_encoder(encoder);
+            }
+
+            if let Some(alignment) = alignment {
+                writer.with_alignment(alignment);
+            }
+
+            if let Some(block_size) = block_size {
+                writer.with_block_size(block_size);
+            }
+
+            if let Some(previous) = previous
+                && let Err(err) = writer.with_previous_archive(previous)
+            {
+                return fail(err);
+            }
+
+            if let Some(threads) = threads {
+                writer.with_threads(threads);
+            }
+
+            writer.minify_metadata(!no_minify_metadata);
+
+            match base {
+                Some(base) => writer.add_patch_from_dirs(base, &input),
+                None => writer.add_paths_from_dir(&input),
+            }
+            .and_then(|writer| writer.build())
+        }
+        Command::List { archive } => HpakReader::new(&archive).map(|reader| list(&reader)),
+        Command::Extract {
+            archive,
+            paths,
+            output,
+        } => HpakReader::new(&archive).and_then(|reader| extract(&reader, paths, &output)),
+        Command::Info { archive } => {
+            HpakReader::new(&archive).and_then(|reader| info(&archive, &reader))
+        }
+        Command::Migrate { archive, output } => migrate_archive(&archive, &output),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => fail(err),
+    }
+}
+
+fn fail(err: bevy_histrion_packer::Error) -> ExitCode {
+    eprintln!("error: {err}");
+    ExitCode::FAILURE
+}
+
+fn list(reader: &HpakReader) {
+    let mut files = reader.iter_files().collect::<Vec<_>>();
+    files.sort_by(|a, b| a.path().cmp(b.path()));
+
+    println!(
+        "{:<12} {:>12} {:>12} {:>10}  path",
+        "compression", "size", "compressed", "meta"
+    );
+
+    for file in files {
+        println!(
+            "{:<12} {:>12} {:>12} {:>10}  {}",
+            compression_method_name(file.compression_method()),
+            reader
+                .uncompressed_size(file.path())
+                .map_or_else(|_| "?".to_string(), |size| size.to_string()),
+            file.compressed_size(),
+            file.meta_size(),
+            file.path().display()
+        );
+    }
+
+    let mut tombstones = reader.iter_tombstones().collect::<Vec<_>>();
+    tombstones.sort();
+
+    for path in tombstones {
+        println!(
+            "{:<12} {:>12} {:>12} {:>10}  {}",
+            "deleted",
+            "-",
+            "-",
+            "-",
+            path.display()
+        );
+    }
+}
+
+fn extract(
+    reader: &HpakReader,
+    paths: Vec<PathBuf>,
+    output: &Path,
+) -> bevy_histrion_packer::Result<()> {
+    let paths = if paths.is_empty() {
+        reader
+            .iter_files()
+            .map(|file| file.path().to_path_buf())
+            .collect()
+    } else {
+        paths
+    };
+
+    // check every path upfront so nothing is extracted from a malicious archive
+    let targets = paths
+        .iter()
+        .map(|path| extract_target(output, path))
+        .collect::<std::io::Result<Vec<_>>>()?;
+
+    for (path, target) in paths.into_iter().zip(targets) {
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        copy(BlockOn::new(reader.read_data(&path)?), &target)?;
+
+        let mut meta_path = target.into_os_string();
+        meta_path.push(".meta");
+
+        match reader.read_meta(&path) {
+            Ok(meta) => copy(BlockOn::new(meta), Path::new(&meta_path))?,
+            Err(bevy_histrion_packer::Error::MetaNotFound(_)) => {}
+            Err(err) => return Err(err),
+        }
+
+        println!("{}", path.display());
+    }
+
+    Ok(())
+}
+
+/// Returns the path `path` is extracted to in `output`, rejecting absolute paths
+/// and paths with `..` that would escape it.
+fn extract_target(output: &Path, path: &Path) -> std::io::Result<PathBuf> {
+    let mut components = path.components().filter(|c| *c != Component::CurDir);
+
+    if !components.all(|c| matches!(c, Component::Normal(_))) || path.file_name().is_none() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "refusing to extract `{}` outside of the output directory",
+                path.display()
+            ),
+        ));
+    }
+
+    Ok(output.join(path))
+}
+
+fn copy(mut reader: impl std::io::Read, path: &Path) -> std::io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    std::io::copy(&mut reader, &mut file)?;
+    file.flush()
+}
+
+fn info(path: &Path, reader: &HpakReader) -> bevy_histrion_packer::Result<()> {
+    let archive_size = std::fs::metadata(path)?.len();
+    let uncompressed = reader.total_uncompressed_size()?;
+    let (files, compressed, meta) =
+        reader
+            .iter_files()
+            .fold((0, 0, 0), |(files, compressed, meta), file| {
+                (
+                    files + 1,
+                    compressed + file.compressed_size(),
+                    meta + file.meta_size(),
+                )
+            });
+
+    println!("version:          {}", reader.version());
+    println!(
+        "meta compression: {}",
+        compression_method_name(reader.meta_compression_method())
+    );
+    println!("archive size:     {archive_size}");
+    println!("files:            {files}");
+    println!("deleted files:    {}", reader.iter_tombstones().count());
+    println!("data size:        {uncompressed}");
+    println!("compressed size:  {compressed}");
+    println!("meta size:        {meta}");
+
+    Ok(())
+}
+
+fn compression_method_name(method: CompressionMethod) -> String {
+    match method {
+        CompressionMethod::None => "none".to_string(),
+        CompressionMethod::Zlib => "zlib".to_string(),
+        CompressionMethod::Zstd(level) => format!("zstd:{level}"),
+        CompressionMethod::Lz4 => "lz4".to_string(),
+    }
+}
+
+fn parse_compression_method(value: &str) -> Result<CompressionMethod, String> {
+    match value.split_once(':') {
+        None => match value {
+            "none" => Ok(CompressionMethod::None),
+            "zlib" => Ok(CompressionMethod::Zlib),
+            "zstd" => Ok(CompressionMethod::Zstd(0)),
+            "lz4" => Ok(CompressionMethod::Lz4),
+            _ => Err(format!(
+                "unknown compression method `{value}`, expected none, zlib, zstd, zstd:<level> or lz4"
+            )),
+        },
+        Some(("zstd", level)) => level
+            .parse()
+            .map(CompressionMethod::Zstd)
+            .map_err(|err| format!("invalid zstd level `{level}`: {err}")),
+        Some(_) => Err(format!("unexpected level in `{value}`")),
+    }
+}
+
+fn parse_zlib_encoder(value: &str) -> Result<ZlibEncoder, String> {
+    match value.split_once(':') {
+        None if value == "flate2" => Ok(ZlibEncoder::Flate2(6)),
+        None if value == "zopfli" => Ok(ZlibEncoder::default()),
+        Some(("flate2", level)) => level
+            .parse()
+            .map(ZlibEncoder::Flate2)
+            .map_err(|err| format!("invalid flate2 level `{level}`: {err}")),
+        Some(("zopfli", iterations)) => iterations
+            .parse()
+            .map(ZlibEncoder::Zopfli)
+            .map_err(|err| format!("invalid zopfli iterations `{iterations}`: {err}")),
+        _ => Err(format!(
+            "unknown zlib encoder `{value}`, expected flate2:<level> or zopfli:<iterations>"
+        )),
+    }
+}
+
+fn parse_extension(value: &str) -> Result<(String, CompressionMethod), String> {
+    let (extension, method) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected `<extension>=<method>`, got `{value}`"))?;
+
+    Ok((extension.to_string(), parse_compression_method(method)?))
+}
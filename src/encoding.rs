@@ -39,7 +39,18 @@ macro_rules! num_impl {
     };
 }
 
-num_impl!(u8: 1, u16: 2, u32: 4, u64: 8);
+num_impl!(u8: 1, u16: 2, u32: 4, u64: 8, i32: 4);
+
+/// Maximum number of elements preallocated when decoding a length-prefixed sequence.
+const MAX_PREALLOCATED_LEN: u64 = 4096;
+
+/// Returns the capacity to preallocate for a sequence of `len` decoded elements.
+///
+/// The length is read from the archive and may be corrupted, so the capacity is
+/// capped and the sequence grows as its elements are actually decoded.
+pub(crate) fn preallocated_len(len: u64) -> usize {
+    len.min(MAX_PREALLOCATED_LEN) as usize
+}
 
 #[cfg(feature = "writer")]
 impl Encode for String {
@@ -97,6 +108,32 @@ where
     }
 }
 
+#[cfg(feature = "writer")]
+impl<T> Encode for Option<T>
+where
+    T: Encode,
+{
+    fn encode<W: Write>(&self, mut writer: W) -> Result<usize> {
+        match self {
+            None => 0u8.encode(writer),
+            Some(value) => Ok(1u8.encode(&mut writer)? + value.encode(writer)?),
+        }
+    }
+}
+
+impl<T> Decode for Option<T>
+where
+    T: Decode,
+{
+    fn decode<R: Read>(mut reader: R) -> Result<Self> {
+        match u8::decode(&mut reader)? {
+            0 => Ok(None),
+            1 => T::decode(reader).map(Some),
+            _ => Err(Error::InvalidFileFormat),
+        }
+    }
+}
+
 #[cfg(feature = "writer")]
 impl<T> Encode for Vec<T>
 where
@@ -115,10 +152,13 @@ where
 {
     fn decode<R: Read>(mut reader: R) -> Result<Self> {
         let len = u64::decode(&mut reader)?;
-        (0..len).try_fold(Vec::<T>::with_capacity(len as usize), |mut acc, _| {
-            acc.push(T::decode(&mut reader)?);
-            Ok(acc)
-        })
+        (0..len).try_fold(
+            Vec::<T>::with_capacity(preallocated_len(len)),
+            |mut acc, _| {
+                acc.push(T::decode(&mut reader)?);
+                Ok(acc)
+            },
+        )
     }
 }
 
@@ -143,16 +183,12 @@ impl Encode for PathBuf {
                 buf.push('/');
             }
 
-            #[cfg(windows)]
+            // drop the separator pushed after the last component, unless the path ends with one
+            if !self.to_string_lossy().ends_with(std::path::is_separator)
+                && buf != "/"
+                && buf.ends_with('/')
             {
-                use std::os::windows::ffi::OsStrExt as _;
-
-                if self.as_os_str().encode_wide().last() != Some(std::path::MAIN_SEPARATOR as u16)
-                    && buf != "/"
-                    && buf.ends_with('/')
-                {
-                    buf.pop();
-                }
+                buf.pop();
             }
 
             buf
@@ -183,11 +219,15 @@ mod tests {
     #[case(u32::MAX)]
     #[case(u64::MIN)]
     #[case(u64::MAX)]
+    #[case(i32::MIN)]
+    #[case(i32::MAX)]
     #[case(String::from("Hello World!"))]
     #[case(PathBuf::from("Hello/World"))]
     #[case(String::from("Hello World!"))]
     #[case([u64::MIN, 0u64, u64::MAX])]
     #[case(vec![u64::MIN, 0u64, u64::MAX, 42u64])]
+    #[case(None::<u64>)]
+    #[case(Some([42u8; 12]))]
     #[cfg(feature = "writer")]
     fn it_encode_decode<T: Encode + Decode + PartialEq + Debug>(#[case] value: T) {
         let mut bytes = Vec::new();
@@ -198,6 +238,22 @@ mod tests {
         assert_eq!(value, decoded);
     }
 
+    #[rstest]
+    #[case("Hello/World/my_file.txt", "Hello/World/my_file.txt")]
+    #[case("Hello/World/", "Hello/World/")]
+    #[case("./Hello", "./Hello")]
+    #[case("/", "/")]
+    #[cfg(feature = "writer")]
+    fn it_encode_pathbuf_trailing_separator(#[case] path: &str, #[case] expected: &str) {
+        let mut bytes = Vec::new();
+        let _ = PathBuf::from(path).encode(&mut bytes).unwrap();
+
+        assert_eq!(
+            expected,
+            String::decode(&mut bytes.as_slice()).unwrap().as_str()
+        );
+    }
+
     #[test]
     #[cfg(all(windows, feature = "writer"))]
     fn it_encode_decode_pathbuf_windows() {
@@ -0,0 +1,676 @@
+use super::*;
+use crate::{Error, Result};
+
+/// Size of the nonce of each encrypted entry.
+pub(crate) const NONCE_SIZE: usize = 12;
+
+/// Size of the authentication tag appended to each encrypted segment.
+pub(crate) const TAG_SIZE: usize = 16;
+
+/// Size of the key-check value of encrypted archives, a nonce followed by the
+/// authentication tag of an empty message.
+pub(crate) const KEY_CHECK_SIZE: usize = NONCE_SIZE + TAG_SIZE;
+
+/// Segment of an encrypted entry holding its metadata.
+pub(crate) const META_SEGMENT: u32 = 0;
+
+/// Returns the segment of an encrypted entry holding its data block at `index`,
+/// data stored as a single stream is the block `0`.
+///
+/// Returns `None` past the last segment, whose nonce would be reused otherwise.
+pub(crate) fn data_segment(index: usize) -> Option<u32> {
+    u32::try_from(index).ok()?.checked_add(1)
+}
+
+#[cfg(feature = "encryption")]
+mod cipher {
+    use super::*;
+    use chacha20poly1305::{
+        ChaCha20Poly1305, KeyInit, Nonce,
+        aead::{Aead, Payload},
+    };
+
+    /// Associated data of the key-check value.
+    const KEY_CHECK_AAD: &[u8] = b"hpak key check";
+
+    /// Associated data of the encrypted entry table.
+    const ENTRIES_AAD: &[u8] = b"hpak entries";
+
+    /// Cipher of an archive whose entries are encrypted with ChaCha20-Poly1305.
+    ///
+    /// Each entry is encrypted with its own random nonce and its metadata and
+    /// data blocks are encrypted as separate segments, so blocks can still be
+    /// decrypted independently. The path hash, presence of metadata, number of
+    /// data segments and uncompressed size of the entry are authenticated along
+    /// each segment, so segments can't be dropped from an entry without failing
+    /// to decrypt.
+    #[derive(Clone)]
+    pub(crate) struct ArchiveCipher(ChaCha20Poly1305);
+
+    impl ArchiveCipher {
+        pub(crate) fn new(key: &[u8; 32]) -> Self {
+            Self(ChaCha20Poly1305::new(key.into()))
+        }
+
+        /// Returns the cipher of an archive with the given `key_check` value, if encrypted.
+        ///
+        /// # Errors
+        ///
+        /// Returns [`Error::InvalidKey`] if the archive is encrypted and `key` is
+        /// missing or doesn't match `key_check`.
+        pub(crate) fn for_archive(
+            key: Option<&[u8; 32]>,
+            key_check: Option<&[u8; KEY_CHECK_SIZE]>,
+        ) -> Result<Option<Self>> {
+            match (key, key_check) {
+                (_, None) => Ok(None),
+                (Some(key), Some(key_check)) => {
+                    let cipher = Self::new(key);
+
+                    match cipher.check_key(key_check) {
+                        true => Ok(Some(cipher)),
+                        false => Err(Error::InvalidKey),
+                    }
+                }
+                (None, Some(_)) => Err(Error::InvalidKey),
+            }
+        }
+
+        /// Returns `true` if `key_check` was generated with the key of this cipher.
+        pub(crate) fn check_key(&self, key_check: &[u8; KEY_CHECK_SIZE]) -> bool {
+            let (nonce, tag) = key_check.split_at(NONCE_SIZE);
+
+            self.0
+                .decrypt(
+                    Nonce::from_slice(nonce),
+                    Payload {
+                        msg: tag,
+                        aad: KEY_CHECK_AAD,
+                    },
+                )
+                .is_ok()
+        }
+
+        /// Generate a key-check value for the key of this cipher.
+        #[cfg(feature = "writer")]
+        pub(crate) fn key_check(&self) -> [u8; KEY_CHECK_SIZE] {
+            let nonce = random_nonce();
+            let tag = self
+                .0
+                .encrypt(
+                    Nonce::from_slice(&nonce),
+                    Payload {
+                        msg: &[],
+                        aad: KEY_CHECK_AAD,
+                    },
+                )
+                .expect("encrypting an empty message cannot fail");
+
+            let mut key_check = [0u8; KEY_CHECK_SIZE];
+            key_check[..NONCE_SIZE].copy_from_slice(&nonce);
+            key_check[NONCE_SIZE..].copy_from_slice(&tag);
+            key_check
+        }
+
+        /// Encrypt the encoded entry `table` with a new random nonce, returning the
+        /// encrypted table and its nonce.
+        #[cfg(feature = "writer")]
+        pub(crate) fn encrypt_entries(&self, table: &[u8]) -> Result<(Vec<u8>, [u8; NONCE_SIZE])> {
+            let nonce = random_nonce();
+            let table = self
+                .0
+                .encrypt(
+                    Nonce::from_slice(&nonce),
+                    Payload {
+                        msg: table,
+                        aad: ENTRIES_AAD,
+                    },
+                )
+                .map_err(|_| Error::EncryptionFailed)?;
+
+            Ok((table, nonce))
+        }
+
+        /// Decrypt and authenticate the entry `table` encrypted with `nonce`.
+        ///
+        /// # Errors
+        ///
+        /// Returns [`Error::InvalidFileFormat`] if the table has been tampered with.
+        pub(crate) fn decrypt_entries(
+            &self,
+            nonce: &[u8; NONCE_SIZE],
+            table: &[u8],
+        ) -> Result<Vec<u8>> {
+            self.0
+                .decrypt(
+                    Nonce::from_slice(nonce),
+                    Payload {
+                        msg: table,
+                        aad: ENTRIES_AAD,
+                    },
+                )
+                .map_err(|_| Error::InvalidFileFormat)
+        }
+
+        /// Returns the cipher of `entry`, if encrypted.
+        pub(crate) fn entry(&self, entry: &HpakFileEntry) -> Option<EntryCipher> {
+            entry.nonce.map(|nonce| EntryCipher {
+                cipher: self.0.clone(),
+                nonce,
+                aad: entry_aad(
+                    entry.hash,
+                    entry.meta_size > 0,
+                    entry.blocks.len(),
+                    entry.uncompressed_size,
+                ),
+                path: entry.path.clone(),
+            })
+        }
+
+        /// Encrypt the compressed `meta` and `data` of the entry at `path` with a new
+        /// random nonce, updating the size of each data block, and return the nonce.
+        ///
+        /// Empty metadata is left as is so the entry is still stored without metadata.
+        #[cfg(feature = "writer")]
+        pub(crate) fn encrypt_entry(
+            &self,
+            path: &Path,
+            uncompressed_size: u64,
+            meta: &mut Vec<u8>,
+            data: &mut Vec<u8>,
+            blocks: &mut [u64],
+        ) -> Result<[u8; NONCE_SIZE]> {
+            let cipher = EntryCipher {
+                cipher: self.0.clone(),
+                nonce: random_nonce(),
+                aad: entry_aad(
+                    hash_path(path),
+                    !meta.is_empty(),
+                    blocks.len(),
+                    uncompressed_size,
+                ),
+                path: path.to_path_buf(),
+            };
+
+            if !meta.is_empty() {
+                *meta = cipher.encrypt(META_SEGMENT, meta)?;
+            }
+
+            if blocks.is_empty() {
+                let segment = data_segment(0).ok_or(Error::EncryptionFailed)?;
+                *data = cipher.encrypt(segment, data)?;
+            } else {
+                let mut encrypted = Vec::with_capacity(data.len() + blocks.len() * TAG_SIZE);
+                let mut offset = 0;
+
+                for (index, size) in blocks.iter_mut().enumerate() {
+                    let end = offset + *size as usize;
+                    let segment = data_segment(index).ok_or(Error::EncryptionFailed)?;
+                    let block = cipher.encrypt(segment, &data[offset..end])?;
+
+                    offset = end;
+                    *size = block.len() as u64;
+                    encrypted.extend_from_slice(&block);
+                }
+
+                *data = encrypted;
+            }
+
+            Ok(cipher.nonce)
+        }
+    }
+
+    #[cfg(feature = "debug-impls")]
+    impl std::fmt::Debug for ArchiveCipher {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.write_str("ArchiveCipher")
+        }
+    }
+
+    /// Size of the associated data of the segments of an entry.
+    const ENTRY_AAD_SIZE: usize = 25;
+
+    /// Returns the associated data of the segments of an entry, made of its path
+    /// `hash`, whether it `has_meta`, its number of data segments and its
+    /// `uncompressed_size`.
+    ///
+    /// Empty metadata isn't encrypted, so its absence is authenticated here instead.
+    /// Data stored as a single stream, with no `blocks`, is one segment.
+    fn entry_aad(
+        hash: u64,
+        has_meta: bool,
+        blocks: usize,
+        uncompressed_size: u64,
+    ) -> [u8; ENTRY_AAD_SIZE] {
+        let mut aad = [0u8; ENTRY_AAD_SIZE];
+        aad[..8].copy_from_slice(&hash.to_le_bytes());
+        aad[8] = has_meta as u8;
+        aad[9..17].copy_from_slice(&(blocks.max(1) as u64).to_le_bytes());
+        aad[17..].copy_from_slice(&uncompressed_size.to_le_bytes());
+        aad
+    }
+
+    /// Cipher of the segments of an encrypted entry.
+    pub(crate) struct EntryCipher {
+        cipher: ChaCha20Poly1305,
+        nonce: [u8; NONCE_SIZE],
+        /// Associated data authenticated along each segment, see `entry_aad`.
+        aad: [u8; ENTRY_AAD_SIZE],
+        /// Path of the entry, reported on decryption failures.
+        path: PathBuf,
+    }
+
+    impl EntryCipher {
+        /// Returns the nonce of `segment`, derived from the nonce of the entry.
+        fn nonce(&self, segment: u32) -> Nonce {
+            let mut nonce = self.nonce;
+
+            for (byte, index) in nonce[NONCE_SIZE - 4..]
+                .iter_mut()
+                .zip(segment.to_le_bytes())
+            {
+                *byte ^= index;
+            }
+
+            nonce.into()
+        }
+
+        #[cfg(feature = "writer")]
+        fn encrypt(&self, segment: u32, plaintext: &[u8]) -> Result<Vec<u8>> {
+            self.cipher
+                .encrypt(
+                    &self.nonce(segment),
+                    Payload {
+                        msg: plaintext,
+                        aad: &self.aad,
+                    },
+                )
+                .map_err(|_| Error::EncryptionFailed)
+        }
+
+        /// Decrypt and authenticate the `ciphertext` of `segment`.
+        ///
+        /// # Errors
+        ///
+        /// Returns [`Error::DecryptionFailed`] if the segment has been tampered with.
+        pub(crate) fn decrypt(&self, segment: u32, ciphertext: &[u8]) -> Result<Vec<u8>> {
+            self.cipher
+                .decrypt(
+                    &self.nonce(segment),
+                    Payload {
+                        msg: ciphertext,
+                        aad: &self.aad,
+                    },
+                )
+                .map_err(|_| Error::DecryptionFailed(self.path.clone()))
+        }
+    }
+
+    #[cfg(feature = "debug-impls")]
+    impl std::fmt::Debug for EntryCipher {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("EntryCipher")
+                .field("path", &self.path)
+                .finish_non_exhaustive()
+        }
+    }
+
+    #[cfg(feature = "writer")]
+    fn random_nonce() -> [u8; NONCE_SIZE] {
+        use chacha20poly1305::{AeadCore, aead::OsRng};
+
+        ChaCha20Poly1305::generate_nonce(&mut OsRng).into()
+    }
+}
+
+/// Stand-ins for the ciphers when the `encryption` feature is disabled, they cannot
+/// be constructed so encrypted archives can't be opened.
+#[cfg(not(feature = "encryption"))]
+mod cipher {
+    use super::*;
+
+    #[cfg_attr(feature = "debug-impls", derive(Debug))]
+    #[derive(Clone)]
+    pub(crate) enum ArchiveCipher {}
+
+    impl ArchiveCipher {
+        pub(crate) fn for_archive(
+            _key: Option<&[u8; 32]>,
+            key_check: Option<&[u8; KEY_CHECK_SIZE]>,
+        ) -> Result<Option<Self>> {
+            match key_check {
+                None => Ok(None),
+                Some(_) => Err(Error::InvalidKey),
+            }
+        }
+
+        #[cfg(feature = "writer")]
+        pub(crate) fn check_key(&self, _key_check: &[u8; KEY_CHECK_SIZE]) -> bool {
+            match *self {}
+        }
+
+        #[cfg(feature = "writer")]
+        pub(crate) fn key_check(&self) -> [u8; KEY_CHECK_SIZE] {
+            match *self {}
+        }
+
+        #[cfg(feature = "writer")]
+        pub(crate) fn encrypt_entries(&self, _table: &[u8]) -> Result<(Vec<u8>, [u8; NONCE_SIZE])> {
+            match *self {}
+        }
+
+        pub(crate) fn decrypt_entries(
+            &self,
+            _nonce: &[u8; NONCE_SIZE],
+            _table: &[u8],
+        ) -> Result<Vec<u8>> {
+            match *self {}
+        }
+
+        pub(crate) fn entry(&self, _entry: &HpakFileEntry) -> Option<EntryCipher> {
+            match *self {}
+        }
+
+        #[cfg(feature = "writer")]
+        pub(crate) fn encrypt_entry(
+            &self,
+            _path: &Path,
+            _uncompressed_size: u64,
+            _meta: &mut Vec<u8>,
+            _data: &mut Vec<u8>,
+            _blocks: &mut [u64],
+        ) -> Result<[u8; NONCE_SIZE]> {
+            match *self {}
+        }
+    }
+
+    #[cfg_attr(feature = "debug-impls", derive(Debug))]
+    pub(crate) enum EntryCipher {}
+
+    impl EntryCipher {
+        pub(crate) fn decrypt(&self, _segment: u32, _ciphertext: &[u8]) -> Result<Vec<u8>> {
+            match *self {}
+        }
+    }
+}
+
+pub(crate) use cipher::*;
+
+#[cfg(all(test, feature = "encryption", feature = "writer"))]
+mod tests {
+    use super::*;
+    use crate::{HpakReader, writer::HpakWriter};
+    use bevy::asset::io::{AssetReader, AssetReaderError};
+    use futures_lite::{AsyncReadExt, future::block_on};
+
+    const KEY: [u8; 32] = [7; 32];
+
+    fn write_encrypted_archive(dir: &Path, encrypt_entries: bool) -> PathBuf {
+        write_archive(dir, "assets.hpak", |writer| {
+            writer
+                .meta_compression(CompressionMethod::Zlib)
+                .with_block_size(1024)
+                .with_encryption_key(&KEY)
+                .encrypt_entries_table(encrypt_entries)
+                .add_bytes_with("a.txt", "(a)", "secret a", CompressionMethod::None)
+                .add_bytes_with(
+                    "b.txt",
+                    "(b)",
+                    "secret b".repeat(512),
+                    CompressionMethod::Lz4,
+                )
+                .add_bytes("c.txt", Vec::new(), "secret c");
+        })
+    }
+
+    fn read(reader: &HpakReader, path: &str) -> std::io::Result<(Vec<u8>, Vec<u8>)> {
+        let path = Path::new(path);
+
+        block_on(async {
+            let mut data = Vec::new();
+            reader
+                .read_data(path)
+                .unwrap()
+                .read_to_end(&mut data)
+                .await?;
+
+            let meta = reader.read_meta_bytes(path).await.unwrap_or_default();
+
+            Ok((meta, data))
+        })
+    }
+
+    #[test]
+    fn it_read_encrypted_archive() {
+        let dir = temp_dir("encrypted");
+        let archive = write_encrypted_archive(&dir, false);
+
+        let bytes = std::fs::read(&archive).unwrap();
+        assert!(!bytes.windows(6).any(|w| w == b"secret"));
+
+        let reader = HpakReader::new_encrypted(&archive, &KEY).unwrap();
+        assert!(reader.is_encrypted());
+        assert!(reader.iter_files().all(HpakFileEntry::is_encrypted));
+
+        assert_eq!(
+            (b"(a)".to_vec(), b"secret a".to_vec()),
+            read(&reader, "a.txt").unwrap()
+        );
+        assert_eq!(
+            (b"(b)".to_vec(), "secret b".repeat(512).into_bytes()),
+            read(&reader, "b.txt").unwrap()
+        );
+        assert_eq!(
+            (Vec::new(), b"secret c".to_vec()),
+            read(&reader, "c.txt").unwrap()
+        );
+
+        let report = reader.verify().unwrap();
+        assert!(report.is_ok(), "{:?}", report.issues);
+
+        assert!(matches!(HpakReader::new(&archive), Err(Error::InvalidKey)));
+        assert!(matches!(
+            HpakReader::new_encrypted(&archive, &[8; 32]),
+            Err(Error::InvalidKey)
+        ));
+    }
+
+    /// Flip the first data byte of the entry at `path` in `archive`.
+    fn tamper(archive: &Path, path: &str) {
+        let reader = HpakReader::new_encrypted(archive, &KEY).unwrap();
+        let entry = reader.get_entry(Path::new(path)).unwrap();
+        let data_offset = (entry.meta_offset + entry.meta_size) as usize;
+        drop(reader);
+
+        let mut bytes = std::fs::read(archive).unwrap();
+        bytes[data_offset] ^= 0xFF;
+        std::fs::write(archive, bytes).unwrap();
+    }
+
+    fn is_decryption_failure(err: &std::io::Error) -> bool {
+        matches!(
+            err.get_ref().and_then(|err| err.downcast_ref::<Error>()),
+            Some(Error::DecryptionFailed(path)) if path == Path::new("a.txt")
+        )
+    }
+
+    #[test]
+    fn it_reject_tampered_entries() {
+        let dir = temp_dir("encrypted-tampered");
+
+        // single stream, decrypted when opened
+        let archive = write_archive(&dir, "stream.hpak", |writer| {
+            writer
+                .with_encryption_key(&KEY)
+                .add_bytes("a.txt", "(a)", "secret a");
+        });
+        tamper(&archive, "a.txt");
+
+        let reader = HpakReader::new_encrypted(&archive, &KEY).unwrap();
+        assert!(matches!(
+            reader.read_data(Path::new("a.txt")),
+            Err(Error::DecryptionFailed(path)) if path == Path::new("a.txt")
+        ));
+        assert!(matches!(
+            block_on(AssetReader::read(&reader, Path::new("a.txt"))),
+            Err(AssetReaderError::Io(err)) if is_decryption_failure(&err)
+        ));
+        assert!(!reader.verify().unwrap().is_ok());
+
+        // blocks, decrypted as they are read
+        let archive = write_encrypted_archive(&dir, false);
+        tamper(&archive, "a.txt");
+
+        let reader = HpakReader::new_encrypted(&archive, &KEY).unwrap();
+        assert_eq!(
+            1024,
+            reader.get_entry(Path::new("a.txt")).unwrap().block_size
+        );
+        assert!(is_decryption_failure(&read(&reader, "a.txt").unwrap_err()));
+        assert!(!reader.verify().unwrap().is_ok());
+    }
+
+    #[test]
+    fn it_reject_truncated_entries() {
+        let dir = temp_dir("encrypted-truncated");
+        let archive = write_encrypted_archive(&dir, false);
+
+        let reader = HpakReader::new_encrypted(&archive, &KEY).unwrap();
+        let mut entry = reader.get_entry(Path::new("b.txt")).unwrap().clone();
+        drop(reader);
+
+        // drop the last data block from the entry, as a tampered entry table would
+        let last = entry.blocks.pop().unwrap();
+        assert!(!entry.blocks.is_empty());
+        entry.data_size -= last;
+        entry.uncompressed_size = entry.blocks.len() as u64 * entry.block_size;
+
+        let file = std::fs::File::open(&archive).unwrap();
+        let mmap = std::sync::Arc::new(unsafe { memmap2::Mmap::map(&file).unwrap() });
+        let cipher = ArchiveCipher::new(&KEY).entry(&entry);
+
+        let mut data = Vec::new();
+        let err = block_on(async {
+            HpakEntryReader::for_data(mmap, &entry, cipher)
+                .unwrap()
+                .read_to_end(&mut data)
+                .await
+                .unwrap_err()
+        });
+        assert!(matches!(
+            err.get_ref().and_then(|err| err.downcast_ref::<Error>()),
+            Some(Error::DecryptionFailed(path)) if path == Path::new("b.txt")
+        ));
+    }
+
+    #[test]
+    fn it_reject_stripped_metadata() {
+        let dir = temp_dir("encrypted-stripped-meta");
+        let archive = write_encrypted_archive(&dir, false);
+
+        let reader = HpakReader::new_encrypted(&archive, &KEY).unwrap();
+        let mut entry = reader.get_entry(Path::new("b.txt")).unwrap().clone();
+        drop(reader);
+
+        // drop the metadata from the entry, as a tampered entry table would
+        entry.meta_offset += entry.meta_size;
+        entry.meta_size = 0;
+
+        let file = std::fs::File::open(&archive).unwrap();
+        let mmap = std::sync::Arc::new(unsafe { memmap2::Mmap::map(&file).unwrap() });
+        let cipher = ArchiveCipher::new(&KEY).entry(&entry);
+
+        let mut data = Vec::new();
+        let err = block_on(async {
+            HpakEntryReader::for_data(mmap, &entry, cipher)
+                .unwrap()
+                .read_to_end(&mut data)
+                .await
+                .unwrap_err()
+        });
+        assert!(matches!(
+            err.get_ref().and_then(|err| err.downcast_ref::<Error>()),
+            Some(Error::DecryptionFailed(path)) if path == Path::new("b.txt")
+        ));
+    }
+
+    #[test]
+    fn it_reject_overflowing_segments() {
+        assert_eq!(Some(1), data_segment(0));
+        assert_eq!(Some(u32::MAX), data_segment(u32::MAX as usize - 1));
+        assert_eq!(None, data_segment(u32::MAX as usize));
+        assert_eq!(None, data_segment(usize::MAX));
+    }
+
+    #[test]
+    fn it_reject_overflowing_entries() {
+        let dir = temp_dir("encrypted-overflowing");
+        let archive = write_archive(&dir, "assets.hpak", |writer| {
+            writer
+                .with_encryption_key(&KEY)
+                .add_bytes("a.txt", "(a)", "secret a");
+        });
+
+        let reader = HpakReader::new_encrypted(&archive, &KEY).unwrap();
+        let mut entry = reader.get_entry(Path::new("a.txt")).unwrap().clone();
+        drop(reader);
+
+        entry.meta_offset = u64::MAX - 1;
+
+        let file = std::fs::File::open(&archive).unwrap();
+        let mmap = std::sync::Arc::new(unsafe { memmap2::Mmap::map(&file).unwrap() });
+        let cipher = ArchiveCipher::new(&KEY);
+
+        assert!(matches!(
+            HpakEntryReader::for_meta(mmap, &entry, CompressionMethod::None, cipher.entry(&entry)),
+            Err(Error::InvalidFileFormat)
+        ));
+    }
+
+    #[test]
+    fn it_encrypt_entries_table() {
+        let dir = temp_dir("encrypted-entries");
+        let archive = write_encrypted_archive(&dir, true);
+
+        let bytes = std::fs::read(&archive).unwrap();
+        assert!(!bytes.windows(5).any(|w| w == b"a.txt"));
+
+        assert!(matches!(HpakReader::new(&archive), Err(Error::InvalidKey)));
+        assert!(matches!(
+            HpakReader::new_with_key_provider(&archive, |_| None),
+            Err(Error::InvalidKey)
+        ));
+
+        let reader = HpakReader::new_with_key_provider(&archive, |path| {
+            assert_eq!(archive, path);
+            Some(KEY)
+        })
+        .unwrap();
+        assert_eq!(3, reader.iter_files().count());
+        assert_eq!(
+            (b"(a)".to_vec(), b"secret a".to_vec()),
+            read(&reader, "a.txt").unwrap()
+        );
+
+        let report = reader.verify().unwrap();
+        assert!(report.is_ok(), "{:?}", report.issues);
+        assert_eq!(3, report.entries);
+
+        // only the checksum of the entry table can be checked without the key
+        let report = verify_archive(&archive).unwrap();
+        assert!(report.is_ok(), "{:?}", report.issues);
+        assert_eq!(0, report.entries);
+    }
+
+    #[test]
+    fn it_reject_encrypted_entries_table_without_key() {
+        let dir = temp_dir("encrypted-entries-without-key");
+
+        let result = HpakWriter::new(dir.join("assets.hpak"))
+            .unwrap()
+            .encrypt_entries_table(true)
+            .add_bytes("a.txt", Vec::new(), "a")
+            .build();
+        assert!(matches!(result, Err(Error::InvalidKey)));
+    }
+}
@@ -0,0 +1,294 @@
+use super::*;
+use bevy::{
+    asset::io::{AssetReader, AssetReaderError, PathStream, Reader},
+    platform::collections::HashSet,
+};
+use futures_lite::StreamExt;
+
+/// Where [`HpakHybridReader`] looks for a file first.
+#[cfg_attr(feature = "debug-impls", derive(Debug))]
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum LookupOrder {
+    /// Read the file from the archives, falling back to the loose file on disk if
+    /// it is missing from them.
+    #[default]
+    ArchiveFirst,
+    /// Read the loose file on disk, falling back to the archives if it is missing
+    /// from the filesystem.
+    FilesystemFirst,
+}
+
+/// An [`AssetReader`] serving files from HPAK archives and from loose files on disk.
+///
+/// Each file is looked up following the [`LookupOrder`] of the longest path prefix
+/// configured with [`HpakHybridReader::with_lookup_order`], or in the archives first
+/// if no prefix matches. The metadata of a file is always read from the same place
+/// as its data. Directories list the entries of both the archives and the filesystem.
+///
+/// # Examples
+///
+/// ```no_run
+/// use bevy::asset::io::AssetSource;
+/// use bevy_histrion_packer::{HpakHybridReader, HpakLayeredReader, LookupOrder};
+///
+/// # fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let archive = HpakLayeredReader::default();
+/// archive.mount_path("assets.hpak", 0)?;
+///
+/// // iterate on the shaders without repacking the archive
+/// let reader = HpakHybridReader::new(
+///     archive,
+///     AssetSource::get_default_reader("assets".to_string())(),
+/// )
+/// .with_lookup_order("shaders", LookupOrder::FilesystemFirst);
+/// # Ok(())
+/// # }
+/// ```
+pub struct HpakHybridReader {
+    archive: HpakLayeredReader,
+    filesystem: Box<dyn bevy::asset::io::ErasedAssetReader>,
+    /// Lookup order of the files under each path prefix.
+    lookup_orders: Vec<(PathBuf, LookupOrder)>,
+}
+
+impl HpakHybridReader {
+    /// Create a reader over the files of `archive` and of `filesystem`, looking them
+    /// up in the archives first.
+    pub fn new(
+        archive: HpakLayeredReader,
+        filesystem: Box<dyn bevy::asset::io::ErasedAssetReader>,
+    ) -> Self {
+        Self {
+            archive,
+            filesystem,
+            lookup_orders: Vec::new(),
+        }
+    }
+
+    /// Set the lookup order of the files under `prefix`.
+    ///
+    /// The longest prefix matching a path wins, an empty prefix matches every path.
+    pub fn with_lookup_order(mut self, prefix: impl Into<PathBuf>, order: LookupOrder) -> Self {
+        self.lookup_orders.push((prefix.into(), order));
+        self
+    }
+
+    /// Returns the lookup order of the file at `path`.
+    fn lookup_order(&self, path: &Path) -> LookupOrder {
+        self.lookup_orders
+            .iter()
+            .filter(|(prefix, _)| path.starts_with(prefix))
+            .max_by_key(|(prefix, _)| prefix.components().count())
+            .map_or(LookupOrder::default(), |(_, order)| *order)
+    }
+
+    /// Returns `true` if the file at `path` is served from the archives.
+    async fn serves_from_archive(&self, path: &Path) -> bool {
+        match self.lookup_order(path) {
+            LookupOrder::ArchiveFirst => self.archive.contains(path),
+            // checks that the loose file exists without opening it
+            LookupOrder::FilesystemFirst => matches!(
+                self.filesystem.is_directory(path).await,
+                Err(AssetReaderError::NotFound(_))
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "debug-impls")]
+impl std::fmt::Debug for HpakHybridReader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HpakHybridReader")
+            .field("archive", &self.archive)
+            .field("lookup_orders", &self.lookup_orders)
+            .finish_non_exhaustive()
+    }
+}
+
+impl AssetReader for HpakHybridReader {
+    async fn read<'a>(
+        &'a self,
+        path: &'a Path,
+    ) -> std::result::Result<Box<dyn Reader + 'a>, AssetReaderError> {
+        match self.lookup_order(path) {
+            LookupOrder::ArchiveFirst if self.archive.contains(path) => {
+                Ok(Box::new(self.archive.read(path).await?))
+            }
+            LookupOrder::ArchiveFirst => self.filesystem.read(path).await,
+            LookupOrder::FilesystemFirst => match self.filesystem.read(path).await {
+                Err(AssetReaderError::NotFound(_)) => Ok(Box::new(self.archive.read(path).await?)),
+                result => result,
+            },
+        }
+    }
+
+    async fn read_meta<'a>(
+        &'a self,
+        path: &'a Path,
+    ) -> std::result::Result<Box<dyn Reader + 'a>, AssetReaderError> {
+        if self.serves_from_archive(path).await {
+            Ok(Box::new(self.archive.read_meta(path).await?))
+        } else {
+            self.filesystem.read_meta(path).await
+        }
+    }
+
+    async fn read_meta_bytes<'a>(
+        &'a self,
+        path: &'a Path,
+    ) -> std::result::Result<Vec<u8>, AssetReaderError> {
+        if self.serves_from_archive(path).await {
+            self.archive.read_meta_bytes(path).await
+        } else {
+            self.filesystem.read_meta_bytes(path).await
+        }
+    }
+
+    async fn read_directory<'a>(
+        &'a self,
+        path: &'a Path,
+    ) -> std::result::Result<Box<PathStream>, AssetReaderError> {
+        let mut found = false;
+        let mut entries = Vec::new();
+
+        for stream in [
+            self.archive.read_directory(path).await,
+            self.filesystem.read_directory(path).await,
+        ] {
+            match stream {
+                Ok(stream) => {
+                    found = true;
+                    entries.extend(stream.collect::<Vec<_>>().await);
+                }
+                Err(AssetReaderError::NotFound(_)) => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        if found {
+            let mut seen = HashSet::new();
+            entries.retain(|entry| seen.insert(entry.clone()));
+
+            Ok(Box::new(DirStream(entries)))
+        } else {
+            Err(AssetReaderError::NotFound(path.to_path_buf()))
+        }
+    }
+
+    async fn is_directory<'a>(
+        &'a self,
+        path: &'a Path,
+    ) -> std::result::Result<bool, AssetReaderError> {
+        if self.archive.is_directory(path).await? {
+            return Ok(true);
+        }
+
+        match self.filesystem.is_directory(path).await {
+            Err(AssetReaderError::NotFound(_)) => Ok(false),
+            result => result,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "writer"))]
+mod tests {
+    use super::*;
+    use crate::writer::HpakWriter;
+    use bevy::asset::io::file::FileAssetReader;
+    use futures_lite::future::block_on;
+
+    fn read(reader: &HpakHybridReader, path: &str) -> (String, String) {
+        block_on(async {
+            let path = Path::new(path);
+            let mut data = Vec::new();
+
+            reader
+                .read(path)
+                .await
+                .unwrap()
+                .read_to_end(&mut data)
+                .await
+                .unwrap();
+
+            let meta = reader.read_meta_bytes(path).await.unwrap_or_default();
+
+            (
+                String::from_utf8(meta).unwrap(),
+                String::from_utf8(data).unwrap(),
+            )
+        })
+    }
+
+    #[test]
+    fn it_fall_back_to_loose_files() {
+        let dir = temp_dir("hybrid");
+        let assets = dir.join("assets");
+        std::fs::create_dir_all(assets.join("shaders")).unwrap();
+
+        let archive = dir.join("assets.hpak");
+        HpakWriter::new(&archive)
+            .unwrap()
+            .add_bytes("a.txt", "(archive)", "archive a")
+            .add_bytes("b.txt", Vec::new(), "archive b")
+            .add_bytes("shaders/c.wgsl", "(archive)", "archive c")
+            .add_bytes("shaders/e.wgsl", "(archive)", "archive e")
+            .build()
+            .unwrap();
+
+        for (path, content) in [("a.txt", "disk a"), ("b.txt", "disk b")] {
+            std::fs::write(assets.join(path), content).unwrap();
+            std::fs::write(assets.join(format!("{path}.meta")), "(disk)").unwrap();
+        }
+
+        std::fs::write(assets.join("d.txt"), "disk d").unwrap();
+        std::fs::write(assets.join("shaders/c.wgsl"), "disk c").unwrap();
+        std::fs::write(assets.join("shaders/c.wgsl.meta"), "(disk)").unwrap();
+
+        let layered = HpakLayeredReader::default();
+        layered.mount_path(&archive, 0).unwrap();
+
+        let reader = HpakHybridReader::new(layered, Box::new(FileAssetReader::new(&assets)))
+            .with_lookup_order("shaders", LookupOrder::FilesystemFirst);
+
+        assert_eq!(
+            ("(archive)".into(), "archive a".into()),
+            read(&reader, "a.txt")
+        );
+        // the metadata isn't read from disk for files of the archive without any
+        assert_eq!((String::new(), "archive b".into()), read(&reader, "b.txt"));
+        assert_eq!((String::new(), "disk d".into()), read(&reader, "d.txt"));
+        assert_eq!(
+            ("(disk)".into(), "disk c".into()),
+            read(&reader, "shaders/c.wgsl")
+        );
+        assert_eq!(
+            ("(archive)".into(), "archive e".into()),
+            read(&reader, "shaders/e.wgsl")
+        );
+        assert!(block_on(reader.read(Path::new("e.txt"))).is_err());
+
+        let reader = reader.with_lookup_order("", LookupOrder::FilesystemFirst);
+
+        assert_eq!(("(disk)".into(), "disk a".into()), read(&reader, "a.txt"));
+
+        let mut entries = block_on(async {
+            reader
+                .read_directory(Path::new(""))
+                .await
+                .unwrap()
+                .collect::<Vec<_>>()
+                .await
+        });
+        entries.sort();
+
+        assert_eq!(
+            vec![
+                PathBuf::from("a.txt"),
+                PathBuf::from("b.txt"),
+                PathBuf::from("d.txt"),
+                PathBuf::from("shaders"),
+            ],
+            entries
+        );
+    }
+}
@@ -0,0 +1,383 @@
+use super::*;
+use crate::{Error, Result};
+use bevy::{
+    asset::io::{AssetReader, AssetReaderError, PathStream, Reader},
+    platform::collections::HashSet,
+    prelude::Resource,
+};
+use parking_lot::RwLock;
+use std::sync::Arc;
+
+/// An [`AssetReader`] over several HPAK archives mounted in priority order.
+///
+/// Files are looked up from the layer with the highest priority to the lowest one,
+/// so a file present in several archives is always read from the highest layer,
+/// along with its metadata. Directories list the entries of every layer.
+///
+/// Files deleted by a layer, see [`HpakWriter::add_tombstone`](crate::writer::HpakWriter::add_tombstone),
+/// are hidden in the layers below it, as are the directories left without any file.
+///
+/// This is a cheap handle: clones share the same layers, which makes it possible
+/// to mount new archives at runtime, e.g. through the resource inserted by
+/// [`HistrionPackerPlugin`](crate::HistrionPackerPlugin).
+///
+/// # Examples
+///
+/// ```no_run
+/// use bevy_histrion_packer::HpakLayeredReader;
+///
+/// # fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let reader = HpakLayeredReader::default();
+///
+/// reader.mount_path("base.hpak", 0)?;
+/// reader.mount_path("dlc.hpak", 10)?;
+/// reader.mount_path("hotfix.hpak", 100)?;
+/// # Ok(())
+/// # }
+/// ```
+#[cfg_attr(feature = "debug-impls", derive(Debug))]
+#[derive(Clone, Default, Resource)]
+pub struct HpakLayeredReader {
+    /// Mounted layers, sorted by descending priority.
+    layers: Arc<RwLock<Vec<Layer>>>,
+}
+
+#[cfg_attr(feature = "debug-impls", derive(Debug))]
+struct Layer {
+    priority: i32,
+    reader: Arc<HpakReader>,
+}
+
+impl HpakLayeredReader {
+    /// Mount `reader` with the given `priority`.
+    ///
+    /// Higher priorities override lower ones, an archive mounted with the same
+    /// priority as another one overrides it.
+    pub fn mount(&self, reader: HpakReader, priority: i32) {
+        let mut layers = self.layers.write();
+        let index = layers.partition_point(|layer| layer.priority > priority);
+
+        layers.insert(
+            index,
+            Layer {
+                priority,
+                reader: Arc::new(reader),
+            },
+        );
+    }
+
+    /// Open the HPAK archive at `path` and mount it with the given `priority`.
+    ///
+    /// See [`HpakLayeredReader::mount`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the archive cannot be opened, see [`HpakReader::new`].
+    pub fn mount_path(&self, path: impl AsRef<Path>, priority: i32) -> Result<()> {
+        self.mount(HpakReader::new(path)?, priority);
+        Ok(())
+    }
+
+    /// Returns the number of mounted archives.
+    pub fn len(&self) -> usize {
+        self.layers.read().len()
+    }
+
+    /// Returns `true` if no archive is mounted.
+    pub fn is_empty(&self) -> bool {
+        self.layers.read().is_empty()
+    }
+
+    /// Returns `true` if a layer contains the file at `path` and no higher layer deleted it.
+    pub(crate) fn contains(&self, path: &Path) -> bool {
+        self.layer_for(path).is_ok()
+    }
+
+    /// Returns the archive of the highest layer containing the file at `path`,
+    /// unless a higher layer deleted it.
+    fn layer_for(&self, path: &Path) -> Result<Arc<HpakReader>> {
+        find_layer(&self.layers.read(), path)
+            .map(|layer| layer.reader.clone())
+            .ok_or_else(|| Error::EntryNotFound(path.to_path_buf()))
+    }
+}
+
+/// Returns the highest of `layers` containing the file at `path`, unless a higher
+/// layer deleted it.
+fn find_layer<'a>(layers: &'a [Layer], path: &Path) -> Option<&'a Layer> {
+    for layer in layers {
+        if layer.reader.get_entry(path).is_ok() {
+            return Some(layer);
+        }
+
+        if layer.reader.is_tombstone(path) {
+            break;
+        }
+    }
+
+    None
+}
+
+/// Returns `true` if the directory at `path` contains a file of `layers`, directly or
+/// in a subdirectory, that no higher layer deleted.
+fn has_live_descendants(layers: &[Layer], path: &Path) -> bool {
+    layers.iter().any(|layer| {
+        layer
+            .reader
+            .directory_entries(path)
+            .is_some_and(|entries| entries.iter().any(|entry| is_live(layers, entry)))
+    })
+}
+
+/// Returns `true` if `path` is a file or a directory of `layers` that isn't deleted.
+fn is_live(layers: &[Layer], path: &Path) -> bool {
+    find_layer(layers, path).is_some() || has_live_descendants(layers, path)
+}
+
+impl AssetReader for HpakLayeredReader {
+    async fn read<'a>(
+        &'a self,
+        path: &'a Path,
+    ) -> std::result::Result<Box<dyn Reader + 'a>, AssetReaderError> {
+        Ok(Box::new(self.layer_for(path)?.read_data(path)?))
+    }
+
+    async fn read_meta<'a>(
+        &'a self,
+        path: &'a Path,
+    ) -> std::result::Result<Box<dyn Reader + 'a>, AssetReaderError> {
+        Ok(Box::new(self.layer_for(path)?.read_meta(path)?))
+    }
+
+    async fn read_meta_bytes<'a>(
+        &'a self,
+        path: &'a Path,
+    ) -> std::result::Result<Vec<u8>, AssetReaderError> {
+        self.layer_for(path)?.read_meta_bytes(path).await
+    }
+
+    async fn read_directory<'a>(
+        &'a self,
+        path: &'a Path,
+    ) -> std::result::Result<Box<PathStream>, AssetReaderError> {
+        let layers = self.layers.read();
+        let mut seen = HashSet::new();
+        let mut entries = Vec::new();
+
+        for layer in layers.iter() {
+            if let Some(directory) = layer.reader.directory_entries(path) {
+                entries.extend(
+                    directory
+                        .iter()
+                        .filter(|e| is_live(&layers, e) && seen.insert(*e))
+                        .cloned(),
+                );
+            }
+        }
+
+        // directories whose files were all deleted are hidden too
+        if entries.is_empty() {
+            Err(AssetReaderError::NotFound(path.to_path_buf()))
+        } else {
+            Ok(Box::new(DirStream(entries)))
+        }
+    }
+
+    async fn is_directory<'a>(
+        &'a self,
+        path: &'a Path,
+    ) -> std::result::Result<bool, AssetReaderError> {
+        Ok(has_live_descendants(&self.layers.read(), path))
+    }
+}
+
+#[cfg(all(test, feature = "writer"))]
+mod tests {
+    use super::*;
+    use futures_lite::{StreamExt, future::block_on};
+
+    fn write_layer(dir: &Path, name: &str, files: &[(&str, &str)], tombstones: &[&str]) -> PathBuf {
+        write_archive(dir, name, |writer| {
+            for (path, content) in files {
+                writer.add_bytes(path, format!("({name:?})"), *content);
+            }
+
+            for path in tombstones {
+                writer.add_tombstone(path);
+            }
+        })
+    }
+
+    fn read(reader: &HpakLayeredReader, path: &str) -> (String, String) {
+        block_on(async {
+            let path = Path::new(path);
+            let mut data = Vec::new();
+
+            reader
+                .read(path)
+                .await
+                .unwrap()
+                .read_to_end(&mut data)
+                .await
+                .unwrap();
+
+            let meta = reader.read_meta_bytes(path).await.unwrap();
+
+            (
+                String::from_utf8(meta).unwrap(),
+                String::from_utf8(data).unwrap(),
+            )
+        })
+    }
+
+    #[test]
+    fn it_layer_archives_by_priority() {
+        let dir = temp_dir("layered");
+
+        let base = write_layer(
+            &dir,
+            "base",
+            &[("a.txt", "base a"), ("sub/b.txt", "base b")],
+            &[],
+        );
+        let dlc = write_layer(&dir, "dlc", &[("sub/c.txt", "dlc c")], &[]);
+        let hotfix = write_layer(&dir, "hotfix", &[("a.txt", "hotfix a")], &[]);
+
+        let reader = HpakLayeredReader::default();
+        reader.mount_path(&hotfix, 10).unwrap();
+        reader.mount_path(&base, 0).unwrap();
+
+        assert_eq!(
+            ("(\"hotfix\")".into(), "hotfix a".into()),
+            read(&reader, "a.txt")
+        );
+        assert_eq!(
+            ("(\"base\")".into(), "base b".into()),
+            read(&reader, "sub/b.txt")
+        );
+        assert!(block_on(reader.read(Path::new("sub/c.txt"))).is_err());
+
+        // mounted at runtime through a clone of the reader
+        reader.clone().mount_path(&dlc, 5).unwrap();
+        assert_eq!(3, reader.len());
+        assert_eq!(
+            ("(\"dlc\")".into(), "dlc c".into()),
+            read(&reader, "sub/c.txt")
+        );
+
+        let mut entries = block_on(async {
+            reader
+                .read_directory(Path::new("sub"))
+                .await
+                .unwrap()
+                .collect::<Vec<_>>()
+                .await
+        });
+        entries.sort();
+
+        assert_eq!(
+            vec![PathBuf::from("sub/b.txt"), PathBuf::from("sub/c.txt")],
+            entries
+        );
+        assert!(block_on(reader.is_directory(Path::new("sub"))).unwrap());
+    }
+
+    #[test]
+    fn it_override_equal_priorities_with_the_last_mounted() {
+        let dir = temp_dir("layered-equal");
+
+        let first = write_layer(&dir, "first", &[("a.txt", "first")], &[]);
+        let second = write_layer(&dir, "second", &[("a.txt", "second")], &[]);
+
+        let reader = HpakLayeredReader::default();
+        reader.mount_path(&first, 0).unwrap();
+        reader.mount_path(&second, 0).unwrap();
+
+        assert_eq!("second", read(&reader, "a.txt").1);
+    }
+
+    #[test]
+    fn it_hide_files_deleted_by_higher_layers() {
+        let dir = temp_dir("layered-tombstones");
+
+        let base = write_layer(
+            &dir,
+            "base",
+            &[
+                ("a.txt", "base a"),
+                ("sub/b.txt", "base b"),
+                ("sub/c.txt", "base c"),
+            ],
+            &[],
+        );
+        let patch = write_layer(&dir, "patch", &[("a.txt", "patch a")], &["sub/b.txt"]);
+        let readded = write_layer(&dir, "readded", &[("sub/b.txt", "readded b")], &[]);
+
+        let reader = HpakLayeredReader::default();
+        reader.mount_path(&base, 0).unwrap();
+        reader.mount_path(&patch, 10).unwrap();
+
+        assert_eq!("patch a", read(&reader, "a.txt").1);
+        assert_eq!("base c", read(&reader, "sub/c.txt").1);
+        assert!(block_on(reader.read(Path::new("sub/b.txt"))).is_err());
+        assert!(block_on(reader.read_meta_bytes(Path::new("sub/b.txt"))).is_err());
+
+        let entries = block_on(async {
+            reader
+                .read_directory(Path::new("sub"))
+                .await
+                .unwrap()
+                .collect::<Vec<_>>()
+                .await
+        });
+
+        assert_eq!(vec![PathBuf::from("sub/c.txt")], entries);
+
+        // a layer above the patch brings the file back
+        reader.mount_path(&readded, 20).unwrap();
+        assert_eq!("readded b", read(&reader, "sub/b.txt").1);
+    }
+
+    #[test]
+    fn it_hide_directories_whose_files_are_all_deleted() {
+        let dir = temp_dir("layered-deleted-directories");
+
+        let base = write_layer(
+            &dir,
+            "base",
+            &[
+                ("a.txt", "base a"),
+                ("sub/b.txt", "base b"),
+                ("sub/deep/c.txt", "base c"),
+            ],
+            &[],
+        );
+        let patch = write_layer(
+            &dir,
+            "patch",
+            &[("a.txt", "patch a")],
+            &["sub/b.txt", "sub/deep/c.txt"],
+        );
+
+        let reader = HpakLayeredReader::default();
+        reader.mount_path(&base, 0).unwrap();
+        reader.mount_path(&patch, 10).unwrap();
+
+        for path in ["sub", "sub/deep"] {
+            let path = Path::new(path);
+            assert!(!block_on(reader.is_directory(path)).unwrap());
+            assert!(block_on(reader.read_directory(path)).is_err());
+        }
+
+        let entries = block_on(async {
+            reader
+                .read_directory(Path::new(""))
+                .await
+                .unwrap()
+                .collect::<Vec<_>>()
+                .await
+        });
+
+        assert_eq!(vec![PathBuf::from("a.txt")], entries);
+    }
+}
@@ -0,0 +1,253 @@
+//! Decoding of archives written with previous versions of the format.
+//!
+//! Version 6 archives store a header made of the metadata compression method and
+//! the entries offset, and an entry table running to the end of the archive. Their
+//! file entries store neither their path, recovered from the directory entries,
+//! nor checksums, uncompressed sizes or data blocks. The uncompressed size of
+//! compressed entries is only computed on demand, by decompressing them.
+//!
+//! Paths written on platforms other than Windows end with a trailing separator,
+//! e.g. `sub/b.txt/`, which is removed when decoding.
+
+use super::*;
+use xxhash_rust::xxh3::xxh3_64;
+
+/// Decode the rest of the header of an archive written with the format `version`,
+/// after its magic number and version.
+pub(crate) fn decode_header<R: Read>(version: u32, mut reader: R) -> Result<HpakHeader> {
+    Ok(HpakHeader {
+        version,
+        meta_compression_method: CompressionMethod::decode(&mut reader)?,
+        entries_offset: u64::decode(&mut reader)?,
+        // filled in by `complete_header`
+        entries_size: 0,
+        entries_checksum: 0,
+        key_check: None,
+        entries_nonce: None,
+        user_data: None,
+        signature: None,
+    })
+}
+
+/// Fill in the size and checksum of the entry table, which runs to the end of
+/// the archive's `bytes`.
+pub(crate) fn complete_header(header: &mut HpakHeader, bytes: &[u8]) -> Result<()> {
+    header.entries_size = (bytes.len() as u64)
+        .checked_sub(header.entries_offset)
+        .ok_or(crate::Error::InvalidFileFormat)?;
+    header.entries_checksum = xxh3_64(header.entries_table(bytes)?);
+
+    Ok(())
+}
+
+/// Decode a version 6 entry table.
+pub(crate) fn decode_entries(mut reader: &[u8]) -> Result<HpakEntries> {
+    let directories_len = u64::decode(&mut reader)?;
+    let mut directories = HashTable::new();
+
+    for _ in 0..directories_len {
+        let mut entry = HpakDirectoryEntry::decode(&mut reader)?;
+
+        for path in &mut entry.entries {
+            *path = normalize_path(path);
+        }
+
+        directories.insert_unique(entry.hash, entry, HpakDirectoryEntry::hash);
+    }
+
+    let files_len = u64::decode(&mut reader)?;
+    let mut files = HashTable::new();
+
+    for _ in 0..files_len {
+        let hash = u64::decode(&mut reader)?;
+        let compression_method = CompressionMethod::decode(&mut reader)?;
+        let meta_offset = u64::decode(&mut reader)?;
+        let meta_size = u64::decode(&mut reader)?;
+        let data_size = u64::decode(&mut reader)?;
+
+        let entry = HpakFileEntry {
+            hash,
+            path: PathBuf::new(),
+            compression_method,
+            meta_offset,
+            meta_size,
+            data_size,
+            // unknown until decompressed, see `uncompressed_size`
+            uncompressed_size: match compression_method {
+                CompressionMethod::None => data_size,
+                _ => 0,
+            },
+            block_size: 0,
+            blocks: Vec::new(),
+            meta_checksum: 0,
+            data_checksum: 0,
+            nonce: None,
+            legacy: true,
+        };
+
+        files.insert_unique(hash, entry, HpakFileEntry::hash);
+    }
+
+    // directories list the full path of their children
+    for directory in directories.iter() {
+        for path in &directory.entries {
+            let hash = hash_path(path);
+
+            if let Some(entry) = files.find_mut(hash, |entry| entry.hash == hash) {
+                entry.path = path.clone();
+            }
+        }
+    }
+
+    Ok(HpakEntries {
+        directories,
+        files,
+        tombstones: HashTable::new(),
+    })
+}
+
+/// Returns the size of the data of the version 6 `entry` of the archive's `bytes`
+/// once decompressed, by decompressing it.
+pub(crate) fn uncompressed_size(bytes: &[u8], entry: &HpakFileEntry) -> Result<u64> {
+    let data = entry
+        .meta_offset
+        .checked_add(entry.meta_size)
+        .and_then(|offset| Some(offset..offset.checked_add(entry.data_size)?))
+        .and_then(|range| bytes.get(range.start as usize..range.end as usize))
+        .ok_or(crate::Error::InvalidFileFormat)?;
+
+    let mut decoder: Box<dyn Read + '_> = match entry.compression_method {
+        CompressionMethod::None => return Ok(data.len() as u64),
+        CompressionMethod::Zlib => Box::new(flate2::read::ZlibDecoder::new(data)),
+        CompressionMethod::Zstd(_) => Box::new(zstd::stream::read::Decoder::with_buffer(data)?),
+        CompressionMethod::Lz4 => Box::new(lz4_flex::frame::FrameDecoder::new(data)),
+    };
+
+    Ok(std::io::copy(&mut decoder, &mut std::io::sink())?)
+}
+
+/// Returns `path` without the trailing separator written by previous versions.
+fn normalize_path(path: &Path) -> PathBuf {
+    path.components().collect()
+}
+
+/// Path of a version 6 archive written by the last release supporting it, on unix.
+///
+/// It holds `a.txt`, with the metadata `(a:1)` and the uncompressed data `none a`,
+/// and `sub/b.txt`, with the metadata `(b:2)` and the zlib compressed data `zlib b`
+/// repeated 64 times. Metadata is compressed with zlib.
+#[cfg(all(test, feature = "writer"))]
+pub(crate) fn v6_archive() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("fuzz/v6.hpak")
+}
+
+#[cfg(all(test, feature = "writer"))]
+mod tests {
+    use super::*;
+    use crate::HpakReader;
+    use bevy::asset::io::AssetReader;
+    use futures_lite::{AsyncReadExt, StreamExt, future::block_on};
+
+    #[test]
+    fn it_read_v6_archive() {
+        let reader = HpakReader::new(v6_archive())
+            .unwrap()
+            .with_checksum_verification(true);
+        assert_eq!(6, reader.version());
+
+        // compare the paths as strings, `Path` equality ignores trailing separators
+        let mut paths = reader
+            .iter_files()
+            .map(|file| file.path().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        paths.sort();
+        assert_eq!(vec!["a.txt", "sub/b.txt"], paths);
+
+        let entries = block_on(async {
+            reader
+                .read_directory(Path::new("sub"))
+                .await
+                .unwrap()
+                .map(|path| path.to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+                .await
+        });
+        assert_eq!(vec!["sub/b.txt"], entries);
+
+        let data = "zlib b".repeat(64);
+
+        for (path, meta, expected) in [("a.txt", "(a:1)", "none a"), ("sub/b.txt", "(b:2)", &data)]
+        {
+            let path = Path::new(path);
+            assert_eq!(
+                expected.len() as u64,
+                reader.uncompressed_size(path).unwrap()
+            );
+
+            block_on(async {
+                let mut bytes = String::new();
+                reader
+                    .read_meta(path)
+                    .unwrap()
+                    .read_to_string(&mut bytes)
+                    .await
+                    .unwrap();
+                assert_eq!(meta, bytes);
+
+                let mut bytes = String::new();
+                reader
+                    .read_data(path)
+                    .unwrap()
+                    .read_to_string(&mut bytes)
+                    .await
+                    .unwrap();
+                assert_eq!(expected, &bytes);
+            });
+        }
+
+        let report = reader.verify().unwrap();
+        assert!(report.is_ok(), "{:?}", report.issues);
+        assert_eq!(2, report.entries);
+        assert_eq!(6 + 384, reader.total_uncompressed_size().unwrap());
+    }
+
+    #[test]
+    fn it_open_v6_archive_with_corrupted_entry() {
+        let dir = temp_dir("legacy-corrupted");
+        let archive = dir.join("assets.hpak");
+
+        let mut bytes = std::fs::read(v6_archive()).unwrap();
+        let entry = HpakReader::new(v6_archive())
+            .unwrap()
+            .get_entry(Path::new("sub/b.txt"))
+            .unwrap()
+            .clone();
+        let data_offset = (entry.meta_offset + entry.meta_size) as usize;
+        bytes[data_offset..data_offset + 2].copy_from_slice(&[0xff, 0xff]);
+        std::fs::write(&archive, bytes).unwrap();
+
+        // sizes are only computed on demand, the corrupted entry fails alone
+        let reader = HpakReader::new(&archive).unwrap();
+        assert_eq!(6, reader.uncompressed_size(Path::new("a.txt")).unwrap());
+        assert!(reader.uncompressed_size(Path::new("sub/b.txt")).is_err());
+        assert!(reader.total_uncompressed_size().is_err());
+
+        let data = reader.read_data(Path::new("sub/b.txt")).unwrap();
+        assert_eq!(None, data.uncompressed_size());
+    }
+
+    #[test]
+    fn it_reject_unsupported_versions() {
+        let dir = temp_dir("legacy-unsupported");
+        let archive = dir.join("assets.hpak");
+
+        let mut bytes = std::fs::read(v6_archive()).unwrap();
+        bytes[4..8].copy_from_slice(&5u32.to_le_bytes());
+        std::fs::write(&archive, bytes).unwrap();
+
+        assert!(matches!(
+            HpakReader::new(&archive),
+            Err(crate::Error::BadVersion(5))
+        ));
+    }
+}
@@ -1,40 +1,171 @@
+mod encryption;
+mod hybrid;
+mod layered;
+mod legacy;
 mod reader;
+mod signing;
+mod verify;
 #[cfg(feature = "writer")]
 pub mod writer;
 
 use std::{
     hash::{Hash, Hasher},
-    io::{Read, Write},
+    io::Read,
     path::{Path, PathBuf},
 };
+#[cfg(feature = "writer")]
+use std::{io::Write, num::NonZeroU64};
 
 use bevy::platform::collections::HashTable;
 use xxhash_rust::xxh3::Xxh3;
 
 use crate::{Result, encoding::*};
 
+pub(crate) use encryption::*;
+pub use hybrid::*;
+pub use layered::*;
 pub use reader::*;
+pub(crate) use signing::*;
+pub use verify::*;
+
+/// Features of an archive that readers must support to read it, stored as a
+/// bitfield in the header.
+///
+/// Readers reject archives using features they don't know, unlike header sections
+/// they don't know which are skipped.
+pub(crate) mod features {
+    /// The metadata and data of the entries are encrypted.
+    pub(crate) const ENCRYPTED_ENTRIES: u64 = 1 << 0;
+    /// The entry table is encrypted.
+    pub(crate) const ENCRYPTED_TABLE: u64 = 1 << 1;
+
+    /// Every feature supported by this version of the crate.
+    pub(crate) const SUPPORTED: u64 = ENCRYPTED_ENTRIES | ENCRYPTED_TABLE;
+}
+
+/// Kinds of the typed, length-prefixed sections of the header.
+pub(crate) mod section {
+    /// Offset, size and XXH3 checksum of the entry table.
+    pub(crate) const ENTRIES: u16 = 0;
+    /// Key-check value of the encryption key.
+    pub(crate) const KEY_CHECK: u16 = 1;
+    /// Nonce of the encrypted entry table.
+    pub(crate) const ENTRIES_NONCE: u16 = 2;
+    /// Arbitrary bytes set by the application.
+    pub(crate) const USER_DATA: u16 = 3;
+    /// Ed25519 signature of the header and entry table, always the last section.
+    pub(crate) const SIGNATURE: u16 = 4;
+}
 
 /// Header structure of an HPAK archive file.
 ///
-/// The header is located at the beginning of the file and contains metadata
-/// about the archive format and where to find the entry table.
-#[cfg_attr(feature = "debug-impls", derive(Debug))]
+/// The header is located at the beginning of the file. After the magic number and
+/// version, it stores the features used by the archive, the metadata compression
+/// method and a table of sections, each prefixed with its kind and size. Readers
+/// skip the sections they don't know and the trailing bytes of the ones they know,
+/// so new sections and fields can be added without breaking them.
+#[cfg_attr(any(test, feature = "debug-impls"), derive(Debug))]
 #[derive(Clone)]
 pub struct HpakHeader {
+    /// Version of the format the archive was written with.
+    pub(crate) version: u32,
     /// Metadata compression method.
     pub(crate) meta_compression_method: CompressionMethod,
     /// Offset of the entry table in the archive.
     pub(crate) entries_offset: u64,
+    /// Size of the entry table in bytes.
+    pub(crate) entries_size: u64,
+    /// XXH3 checksum of the entry table.
+    pub(crate) entries_checksum: u64,
+    /// Key-check value of the encryption key, `None` if the entries are not encrypted.
+    pub(crate) key_check: Option<[u8; KEY_CHECK_SIZE]>,
+    /// Nonce of the encrypted entry table, `None` if the entry table is stored in clear.
+    pub(crate) entries_nonce: Option<[u8; NONCE_SIZE]>,
+    /// Arbitrary bytes set by the application, `None` if not set.
+    pub(crate) user_data: Option<Vec<u8>>,
+    /// Ed25519 signature of the header and entry table, `None` if the archive is not signed.
+    ///
+    /// This is always the last section of the header, the signature covers the bytes
+    /// of the header that precede it.
+    pub(crate) signature: Option<[u8; SIGNATURE_SIZE]>,
+}
+
+impl HpakHeader {
+    /// Decode the header from the whole archive's `bytes`, returning it along with
+    /// its encoded size.
+    pub(crate) fn from_archive(bytes: &[u8]) -> Result<(Self, usize)> {
+        let mut reader = bytes;
+        let mut header = Self::decode(&mut reader)?;
+        let size = bytes.len() - reader.len();
+
+        if header.version < crate::VERSION {
+            legacy::complete_header(&mut header, bytes)?;
+        }
+
+        Ok((header, size))
+    }
+
+    /// Returns the features used by the archive.
+    pub(crate) fn features(&self) -> u64 {
+        let mut features = 0;
+
+        if self.key_check.is_some() {
+            features |= features::ENCRYPTED_ENTRIES;
+        }
+
+        if self.entries_nonce.is_some() {
+            features |= features::ENCRYPTED_TABLE;
+        }
+
+        features
+    }
+
+    /// Returns the entry table bytes from the whole archive's `bytes`.
+    pub(crate) fn entries_table<'a>(&self, bytes: &'a [u8]) -> Result<&'a [u8]> {
+        let start = self.entries_offset as usize;
+        let end = start.checked_add(self.entries_size as usize);
+
+        end.and_then(|end| bytes.get(start..end))
+            .ok_or(crate::Error::InvalidFileFormat)
+    }
 }
 
 #[cfg(feature = "writer")]
 impl Encode for HpakHeader {
     fn encode<W: Write>(&self, mut writer: W) -> crate::Result<usize> {
-        Ok(crate::MAGIC.encode(&mut writer)?
-            + crate::VERSION.encode(&mut writer)?
+        let mut entries = Vec::new();
+        self.entries_offset.encode(&mut entries)?;
+        self.entries_size.encode(&mut entries)?;
+        self.entries_checksum.encode(&mut entries)?;
+
+        let sections = [
+            (section::ENTRIES, Some(&entries[..])),
+            (section::KEY_CHECK, self.key_check.as_ref().map(|v| &v[..])),
+            (
+                section::ENTRIES_NONCE,
+                self.entries_nonce.as_ref().map(|v| &v[..]),
+            ),
+            (section::USER_DATA, self.user_data.as_deref()),
+            (section::SIGNATURE, self.signature.as_ref().map(|v| &v[..])),
+        ];
+        let sections = sections
+            .into_iter()
+            .filter_map(|(kind, payload)| payload.map(|payload| (kind, payload)))
+            .collect::<Vec<_>>();
+
+        let mut size = crate::MAGIC.encode(&mut writer)?
+            + self.version.encode(&mut writer)?
+            + self.features().encode(&mut writer)?
             + self.meta_compression_method.encode(&mut writer)?
-            + self.entries_offset.encode(&mut writer)?)
+            + (sections.len() as u32).encode(&mut writer)?;
+
+        for (kind, payload) in sections {
+            size += kind.encode(&mut writer)? + (payload.len() as u64).encode(&mut writer)?;
+            writer.write_all(payload)?;
+            size += payload.len();
+        }
+
+        Ok(size)
     }
 }
 
@@ -48,14 +179,73 @@ impl Decode for HpakHeader {
 
         let version = u32::decode(&mut reader)?;
 
-        if version != crate::VERSION {
-            return Err(crate::Error::BadVersion(version));
+        match version {
+            crate::VERSION => {}
+            crate::MIN_VERSION..crate::VERSION => return legacy::decode_header(version, reader),
+            _ => return Err(crate::Error::BadVersion(version)),
         }
 
-        Ok(Self {
+        let features = u64::decode(&mut reader)?;
+
+        if features & !features::SUPPORTED != 0 {
+            return Err(crate::Error::UnsupportedFeatures(
+                features & !features::SUPPORTED,
+            ));
+        }
+
+        let mut header = Self {
+            version,
             meta_compression_method: CompressionMethod::decode(&mut reader)?,
-            entries_offset: u64::decode(&mut reader)?,
-        })
+            entries_offset: 0,
+            entries_size: 0,
+            entries_checksum: 0,
+            key_check: None,
+            entries_nonce: None,
+            user_data: None,
+            signature: None,
+        };
+
+        let mut has_entries = false;
+
+        for _ in 0..u32::decode(&mut reader)? {
+            let kind = u16::decode(&mut reader)?;
+            let size = u64::decode(&mut reader)?;
+            let mut payload = (&mut reader).take(size);
+
+            match kind {
+                section::ENTRIES => {
+                    header.entries_offset = u64::decode(&mut payload)?;
+                    header.entries_size = u64::decode(&mut payload)?;
+                    header.entries_checksum = u64::decode(&mut payload)?;
+                    has_entries = true;
+                }
+                section::KEY_CHECK => header.key_check = Some(Decode::decode(&mut payload)?),
+                section::ENTRIES_NONCE => {
+                    header.entries_nonce = Some(Decode::decode(&mut payload)?);
+                }
+                section::USER_DATA => {
+                    let mut user_data = Vec::new();
+                    payload.read_to_end(&mut user_data)?;
+                    header.user_data = Some(user_data);
+                }
+                section::SIGNATURE => header.signature = Some(Decode::decode(&mut payload)?),
+                // sections added by later versions of the format
+                _ => {}
+            }
+
+            // skip unknown sections, and fields appended to known ones
+            std::io::copy(&mut payload, &mut std::io::sink())?;
+
+            if payload.limit() > 0 {
+                return Err(crate::Error::InvalidFileFormat);
+            }
+        }
+
+        if !has_entries || header.features() != features {
+            return Err(crate::Error::InvalidFileFormat);
+        }
+
+        Ok(header)
     }
 }
 
@@ -63,11 +253,13 @@ impl Decode for HpakHeader {
 ///
 /// Each file entry contains metadata about a file stored in the archive,
 /// including its location, size, and compression method.
-#[cfg_attr(feature = "debug-impls", derive(Debug))]
+#[cfg_attr(any(test, feature = "debug-impls"), derive(Debug))]
 #[derive(Clone, PartialEq)]
 pub struct HpakFileEntry {
     /// Hash of the entry's path.
     pub(crate) hash: u64,
+    /// Path of the entry in the archive.
+    pub(crate) path: PathBuf,
     /// Data compression method.
     pub(crate) compression_method: CompressionMethod,
     /// Offset of the metadata in the archive.
@@ -76,6 +268,21 @@ pub struct HpakFileEntry {
     pub(crate) meta_size: u64,
     /// Size of the data. Data is located after the metadata.
     pub(crate) data_size: u64,
+    /// Size of the data once decompressed.
+    pub(crate) uncompressed_size: u64,
+    /// Uncompressed size of each data block, `0` if the data is stored as a single stream.
+    pub(crate) block_size: u64,
+    /// Compressed size of each data block, in order.
+    pub(crate) blocks: Vec<u64>,
+    /// XXH3 digest of the uncompressed metadata.
+    pub(crate) meta_checksum: u64,
+    /// XXH3 digest of the uncompressed data.
+    pub(crate) data_checksum: u64,
+    /// Nonce of the encrypted metadata and data, `None` if the entry is not encrypted.
+    pub(crate) nonce: Option<[u8; NONCE_SIZE]>,
+    /// Whether the entry comes from an archive written with a previous format version,
+    /// which stores neither its checksums nor the uncompressed size of its data. Not encoded.
+    pub(crate) legacy: bool,
 }
 
 impl HpakFileEntry {
@@ -83,16 +290,56 @@ impl HpakFileEntry {
     pub const fn hash(&self) -> u64 {
         self.hash
     }
+
+    /// Returns the path of the file in the archive.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the compression method of the file's data.
+    pub const fn compression_method(&self) -> CompressionMethod {
+        self.compression_method
+    }
+
+    /// Returns the size of the file's metadata as stored in the archive.
+    pub const fn meta_size(&self) -> u64 {
+        self.meta_size
+    }
+
+    /// Returns the size of the file's data as stored in the archive.
+    pub const fn compressed_size(&self) -> u64 {
+        self.data_size
+    }
+
+    /// Returns the size of the file's data once decompressed.
+    ///
+    /// This is `0` for compressed files of archives written with a previous format
+    /// version, which don't store it, see [`HpakReader::uncompressed_size`] to compute it.
+    pub const fn uncompressed_size(&self) -> u64 {
+        self.uncompressed_size
+    }
+
+    /// Returns `true` if the file's metadata and data are encrypted.
+    pub const fn is_encrypted(&self) -> bool {
+        self.nonce.is_some()
+    }
 }
 
 #[cfg(feature = "writer")]
 impl Encode for HpakFileEntry {
     fn encode<W: Write>(&self, mut writer: W) -> crate::Result<usize> {
         Ok(self.hash.encode(&mut writer)?
+            + self.path.encode(&mut writer)?
             + self.compression_method.encode(&mut writer)?
             + self.meta_offset.encode(&mut writer)?
             + self.meta_size.encode(&mut writer)?
-            + self.data_size.encode(&mut writer)?)
+            + self.data_size.encode(&mut writer)?
+            + self.uncompressed_size.encode(&mut writer)?
+            + self.block_size.encode(&mut writer)?
+            + self.blocks.encode(&mut writer)?
+            + self.meta_checksum.encode(&mut writer)?
+            + self.data_checksum.encode(&mut writer)?
+            + self.nonce.encode(&mut writer)?)
     }
 }
 
@@ -100,10 +347,18 @@ impl Decode for HpakFileEntry {
     fn decode<R: std::io::Read>(mut reader: R) -> crate::Result<Self> {
         Ok(Self {
             hash: u64::decode(&mut reader)?,
+            path: PathBuf::decode(&mut reader)?,
             compression_method: CompressionMethod::decode(&mut reader)?,
             meta_offset: u64::decode(&mut reader)?,
             meta_size: u64::decode(&mut reader)?,
             data_size: u64::decode(&mut reader)?,
+            uncompressed_size: u64::decode(&mut reader)?,
+            block_size: u64::decode(&mut reader)?,
+            blocks: Vec::<u64>::decode(&mut reader)?,
+            meta_checksum: u64::decode(&mut reader)?,
+            data_checksum: u64::decode(&mut reader)?,
+            nonce: Option::decode(&mut reader)?,
+            legacy: false,
         })
     }
 }
@@ -112,7 +367,7 @@ impl Decode for HpakFileEntry {
 ///
 /// Directory entries store information about the contents of a directory,
 /// allowing for efficient directory listing operations.
-#[cfg_attr(feature = "debug-impls", derive(Debug))]
+#[cfg_attr(any(test, feature = "debug-impls"), derive(Debug))]
 #[derive(Clone, PartialEq)]
 pub struct HpakDirectoryEntry {
     /// Hash of the entry's path.
@@ -144,16 +399,98 @@ impl Decode for HpakDirectoryEntry {
     }
 }
 
-/// Collection of all entries (files and directories) in an HPAK archive.
+/// Collection of all entries (files, directories and tombstones) in an HPAK archive.
 ///
 /// This structure uses hash tables for O(1) lookups of entries by path hash.
-#[cfg_attr(feature = "debug-impls", derive(Debug))]
+#[cfg_attr(any(test, feature = "debug-impls"), derive(Debug))]
 #[derive(Clone)]
 pub struct HpakEntries {
     /// Directory entries in the archive.
     pub(crate) directories: HashTable<HpakDirectoryEntry>,
     /// File entries in the archive.
     pub(crate) files: HashTable<HpakFileEntry>,
+    /// Paths of the files deleted by this archive, hiding them in the archives
+    /// it is layered on top of.
+    pub(crate) tombstones: HashTable<PathBuf>,
+}
+
+impl HpakEntries {
+    /// Decode the entry `table` of an archive written with the format `version`.
+    pub(crate) fn decode_version(table: &[u8], version: u32) -> Result<Self> {
+        match version {
+            crate::VERSION => Self::decode(table),
+            _ => legacy::decode_entries(table),
+        }
+    }
+
+    /// Find the file entry stored at `path`.
+    ///
+    /// The hash only locates candidates: the stored path must match as well, so
+    /// that a hash collision can never resolve to another file.
+    pub(crate) fn find_file(&self, path: &Path) -> Option<&HpakFileEntry> {
+        let hash = hash_path(path);
+
+        self.files
+            .find(hash, |entry| entry.hash == hash && entry.path == path)
+    }
+
+    /// Returns `true` if the archive deletes the file at `path`.
+    pub(crate) fn is_tombstone(&self, path: &Path) -> bool {
+        self.tombstones
+            .find(hash_path(path), |tombstone| tombstone == path)
+            .is_some()
+    }
+
+    /// Insert a tombstone for the file at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::HashCollision`](crate::Error::HashCollision) if another
+    /// tombstone with the same path hash is already present.
+    #[cfg(feature = "writer")]
+    pub(crate) fn insert_tombstone(&mut self, path: PathBuf) -> Result<()> {
+        use bevy::platform::collections::hash_table::Entry;
+
+        let hash = hash_path(&path);
+
+        match self.tombstones.entry(
+            hash,
+            |other| hash_path(other) == hash,
+            |other| hash_path(other),
+        ) {
+            Entry::Occupied(other) => Err(crate::Error::HashCollision(other.get().clone(), path)),
+            Entry::Vacant(vacant) => {
+                vacant.insert(path);
+                Ok(())
+            }
+        }
+    }
+
+    /// Insert a file entry in the file table.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::HashCollision`](crate::Error::HashCollision) if another
+    /// file with the same path hash is already present.
+    #[cfg(feature = "writer")]
+    pub(crate) fn insert_file(&mut self, entry: HpakFileEntry) -> Result<()> {
+        use bevy::platform::collections::hash_table::Entry;
+
+        match self.files.entry(
+            entry.hash,
+            |other| other.hash == entry.hash,
+            HpakFileEntry::hash,
+        ) {
+            Entry::Occupied(other) => Err(crate::Error::HashCollision(
+                other.get().path.clone(),
+                entry.path,
+            )),
+            Entry::Vacant(vacant) => {
+                vacant.insert(entry);
+                Ok(())
+            }
+        }
+    }
 }
 
 #[cfg(feature = "writer")]
@@ -169,14 +506,19 @@ impl Encode for HpakEntries {
             Ok(v.encode(&mut writer)? + acc) as Result<usize>
         })?;
 
-        Ok(directories_len + directories + entries_len + entries)
+        let tombstones_len = (self.tombstones.len() as u64).encode(&mut writer)?;
+        let tombstones = self.tombstones.iter().try_fold(0usize, |acc, v| {
+            Ok(v.encode(&mut writer)? + acc) as Result<usize>
+        })?;
+
+        Ok(directories_len + directories + entries_len + entries + tombstones_len + tombstones)
     }
 }
 
 impl Decode for HpakEntries {
     fn decode<R: Read>(mut reader: R) -> Result<Self> {
         let directories_len = u64::decode(&mut reader)?;
-        let mut directories = HashTable::with_capacity(directories_len as usize);
+        let mut directories = HashTable::with_capacity(preallocated_len(directories_len));
 
         for _ in 0..directories_len {
             let entry = HpakDirectoryEntry::decode(&mut reader)?;
@@ -184,16 +526,25 @@ impl Decode for HpakEntries {
         }
 
         let entries_len = u64::decode(&mut reader)?;
-        let mut entries = HashTable::with_capacity(entries_len as usize);
+        let mut entries = HashTable::with_capacity(preallocated_len(entries_len));
 
         for _ in 0..entries_len {
             let entry = HpakFileEntry::decode(&mut reader)?;
             entries.insert_unique(entry.hash, entry, HpakFileEntry::hash);
         }
 
+        let tombstones_len = u64::decode(&mut reader)?;
+        let mut tombstones = HashTable::new();
+
+        for _ in 0..tombstones_len {
+            let path = PathBuf::decode(&mut reader)?;
+            tombstones.insert_unique(hash_path(&path), path, |path| hash_path(path));
+        }
+
         Ok(Self {
             directories,
             files: entries,
+            tombstones,
         })
     }
 }
@@ -203,31 +554,86 @@ impl Decode for HpakEntries {
 /// The compression method affects both the storage size and the decompression
 /// performance when loading assets.
 #[repr(u8)]
-#[cfg_attr(feature = "debug-impls", derive(Debug))]
+#[cfg_attr(any(test, feature = "debug-impls"), derive(Debug))]
 #[derive(Copy, Clone, Eq, PartialEq, Hash, Default)]
 pub enum CompressionMethod {
     /// No compression.
     #[default]
     None = 0,
 
-    /// Zlib compression using zopfli for maximum compression ratio.
+    /// Zlib compression, using zopfli for maximum compression ratio by default.
     ///
     /// This provides excellent compression at the cost of slow compression times.
     /// Since compression is done at build time, this is ideal for production builds
-    /// where smaller file sizes are preferred over build speed.
+    /// where smaller file sizes are preferred over build speed. The encoder can be
+    /// changed with [`ZlibEncoder`] for faster builds.
     Zlib = 1,
+
+    /// Zstandard compression at the given level.
+    ///
+    /// Offers compression ratios close to [`CompressionMethod::Zlib`] with much faster
+    /// decompression, which makes it a good fit for large assets such as textures and audio.
+    /// A level of `0` selects zstd's default level.
+    Zstd(i32) = 2,
+
+    /// LZ4 compression using the frame format.
+    ///
+    /// Trades compression ratio for very fast decompression, making it suitable
+    /// for assets that must be streamed within a tight frame budget.
+    Lz4 = 3,
 }
 
+#[cfg(feature = "writer")]
 impl CompressionMethod {
     /// Compress data from `reader` and write to `writer`, returning the number of bytes written.
-    pub(crate) fn compress<R: Read, W: Write>(&self, mut reader: R, mut writer: W) -> Result<u64> {
+    ///
+    /// `zlib` selects the encoder used for [`CompressionMethod::Zlib`].
+    pub(crate) fn compress<R: Read, W: Write>(
+        &self,
+        zlib: ZlibEncoder,
+        mut reader: R,
+        mut writer: W,
+    ) -> Result<u64> {
         match self {
             CompressionMethod::None => Ok(std::io::copy(&mut reader, &mut writer)?),
             CompressionMethod::Zlib => {
-                use zopfli::{Format::Zlib, Options};
+                let mut writer = write_counter::WriterCounter::new(writer);
+
+                match zlib {
+                    ZlibEncoder::Flate2(level) => {
+                        let mut encoder = flate2::write::ZlibEncoder::new(
+                            &mut writer,
+                            flate2::Compression::new(level.min(9)),
+                        );
+                        std::io::copy(&mut reader, &mut encoder)?;
+                        encoder.finish()?;
+                    }
+                    ZlibEncoder::Zopfli(iterations) => {
+                        use zopfli::{Format::Zlib, Options};
+
+                        let options = Options {
+                            iteration_count: NonZeroU64::new(iterations).unwrap_or(NonZeroU64::MIN),
+                            ..Default::default()
+                        };
+
+                        zopfli::compress(options, Zlib, &mut reader, &mut writer)?;
+                    }
+                }
 
+                Ok(writer.total_out())
+            }
+            CompressionMethod::Zstd(level) => {
                 let mut writer = write_counter::WriterCounter::new(writer);
-                zopfli::compress(Options::default(), Zlib, &mut reader, &mut writer)?;
+                zstd::stream::copy_encode(&mut reader, &mut writer, *level)?;
+
+                Ok(writer.total_out())
+            }
+            CompressionMethod::Lz4 => {
+                let writer = write_counter::WriterCounter::new(writer);
+                let mut encoder = lz4_flex::frame::FrameEncoder::new(writer);
+                std::io::copy(&mut reader, &mut encoder)?;
+
+                let writer = encoder.finish().map_err(std::io::Error::from)?;
 
                 Ok(writer.total_out())
             }
@@ -235,6 +641,36 @@ impl CompressionMethod {
     }
 }
 
+#[cfg(feature = "writer")]
+/// Encoder used to compress data with [`CompressionMethod::Zlib`].
+///
+/// Both encoders produce standard zlib streams, so the archive format and the
+/// reader are the same whichever one is used.
+#[cfg_attr(any(test, feature = "debug-impls"), derive(Debug))]
+#[derive(Copy, Clone, Eq, PartialEq, Hash)]
+pub enum ZlibEncoder {
+    /// Fast compression using flate2 at the given level, from `0` (no compression)
+    /// to `9` (best compression). Higher levels are clamped to `9`.
+    ///
+    /// Well suited for iterative development builds.
+    Flate2(u32),
+
+    /// Near optimal compression using zopfli with the given number of iterations,
+    /// `0` is treated as `1`.
+    ///
+    /// Much slower than [`ZlibEncoder::Flate2`], well suited for release builds.
+    Zopfli(u64),
+}
+
+#[cfg(feature = "writer")]
+impl Default for ZlibEncoder {
+    /// Zopfli with 15 iterations.
+    fn default() -> Self {
+        ZlibEncoder::Zopfli(15)
+    }
+}
+
+#[cfg(feature = "writer")]
 mod write_counter {
     use std::io::Write;
 
@@ -273,6 +709,8 @@ impl From<CompressionMethod> for u8 {
         match value {
             CompressionMethod::None => 0,
             CompressionMethod::Zlib => 1,
+            CompressionMethod::Zstd(_) => 2,
+            CompressionMethod::Lz4 => 3,
         }
     }
 }
@@ -280,7 +718,12 @@ impl From<CompressionMethod> for u8 {
 #[cfg(feature = "writer")]
 impl Encode for CompressionMethod {
     fn encode<W: Write>(&self, mut writer: W) -> crate::Result<usize> {
-        u8::from(*self).encode(&mut writer)
+        let variant = u8::from(*self).encode(&mut writer)?;
+
+        match self {
+            CompressionMethod::Zstd(level) => Ok(variant + level.encode(&mut writer)?),
+            _ => Ok(variant),
+        }
     }
 }
 
@@ -291,6 +734,8 @@ impl Decode for CompressionMethod {
         match variant {
             0 => Ok(CompressionMethod::None),
             1 => Ok(CompressionMethod::Zlib),
+            2 => Ok(CompressionMethod::Zstd(i32::decode(&mut reader)?)),
+            3 => Ok(CompressionMethod::Lz4),
             _ => Err(crate::Error::InvalidFileFormat),
         }
     }
@@ -305,6 +750,59 @@ pub(crate) fn hash_path<P: AsRef<Path>>(path: P) -> u64 {
 pub(crate) const fn _assert_send<T: Send>() {}
 pub(crate) const fn _assert_sync<T: Sync>() {}
 
+/// Temporary directory of a test, removed with its content when dropped.
+#[cfg(all(test, feature = "writer"))]
+pub(crate) struct TempDir(PathBuf);
+
+#[cfg(all(test, feature = "writer"))]
+impl std::ops::Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+#[cfg(all(test, feature = "writer"))]
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+#[cfg(all(test, feature = "writer"))]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Create an empty temporary directory unique to the calling test.
+#[cfg(all(test, feature = "writer"))]
+pub(crate) fn temp_dir(name: &str) -> TempDir {
+    let dir = std::env::temp_dir().join(format!("hpak-{}-{name}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    TempDir(dir)
+}
+
+/// Build the archive `name` in `dir` with the entries and settings added by `add`,
+/// returning its path.
+#[cfg(all(test, feature = "writer"))]
+pub(crate) fn write_archive(
+    dir: &Path,
+    name: &str,
+    add: impl FnOnce(&mut writer::HpakWriter),
+) -> PathBuf {
+    let archive = dir.join(name);
+    let mut writer = writer::HpakWriter::new(&archive).unwrap();
+
+    add(&mut writer);
+    writer.build().unwrap();
+
+    archive
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -319,49 +817,152 @@ mod tests {
     #[rstest]
     #[case(CompressionMethod::None, 0)]
     #[case(CompressionMethod::Zlib, 42)]
+    #[case(CompressionMethod::Zstd(19), 42)]
     fn it_encode_decode_header(#[case] method: CompressionMethod, #[case] offset: u64) {
         let header = HpakHeader {
+            version: crate::VERSION,
             meta_compression_method: method,
             entries_offset: offset,
+            entries_size: offset * 2,
+            entries_checksum: u64::MAX - offset,
+            key_check: (offset > 0).then_some([42; KEY_CHECK_SIZE]),
+            entries_nonce: (method == CompressionMethod::Zlib).then_some([24; NONCE_SIZE]),
+            user_data: (method != CompressionMethod::None).then(|| b"build 42".to_vec()),
+            signature: (offset > 0).then_some([12; SIGNATURE_SIZE]),
         };
         let decoded = encode_decode(header.clone());
 
+        assert_eq!(header.version, decoded.version);
         assert_eq!(
             header.meta_compression_method,
             decoded.meta_compression_method
         );
         assert_eq!(header.entries_offset, decoded.entries_offset);
+        assert_eq!(header.entries_size, decoded.entries_size);
+        assert_eq!(header.entries_checksum, decoded.entries_checksum);
+        assert_eq!(header.key_check, decoded.key_check);
+        assert_eq!(header.entries_nonce, decoded.entries_nonce);
+        assert_eq!(header.user_data, decoded.user_data);
+        assert_eq!(header.signature, decoded.signature);
+    }
+
+    /// Encode a header with the given features and sections.
+    fn encode_header(features: u64, sections: &[(u16, &[u8])]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        crate::MAGIC.encode(&mut bytes).unwrap();
+        crate::VERSION.encode(&mut bytes).unwrap();
+        features.encode(&mut bytes).unwrap();
+        CompressionMethod::None.encode(&mut bytes).unwrap();
+        (sections.len() as u32).encode(&mut bytes).unwrap();
+
+        for (kind, payload) in sections {
+            kind.encode(&mut bytes).unwrap();
+            (payload.len() as u64).encode(&mut bytes).unwrap();
+            bytes.extend_from_slice(payload);
+        }
+
+        bytes
+    }
+
+    #[test]
+    fn it_skip_unknown_header_sections() {
+        // a later version appending a field to the entries section
+        let entries = [1u64, 2, 3, 4]
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect::<Vec<_>>();
+
+        let bytes = encode_header(
+            0,
+            &[
+                (42, b"unknown"),
+                (section::ENTRIES, &entries),
+                (section::USER_DATA, b"build 42"),
+            ],
+        );
+        let header = HpakHeader::decode(&bytes[..]).unwrap();
+
+        assert_eq!(1, header.entries_offset);
+        assert_eq!(2, header.entries_size);
+        assert_eq!(3, header.entries_checksum);
+        assert_eq!(Some(b"build 42".to_vec()), header.user_data);
+    }
+
+    #[test]
+    fn it_reject_invalid_headers() {
+        let entries = [0u8; 24];
+
+        let bytes = encode_header(1 << 42, &[(section::ENTRIES, &entries)]);
+        assert!(matches!(
+            HpakHeader::decode(&bytes[..]),
+            Err(crate::Error::UnsupportedFeatures(features)) if features == 1 << 42
+        ));
+
+        // missing entries section
+        let bytes = encode_header(0, &[(section::USER_DATA, b"build 42")]);
+        assert!(HpakHeader::decode(&bytes[..]).is_err());
+
+        // encrypted entries without key-check value
+        let bytes = encode_header(features::ENCRYPTED_ENTRIES, &[(section::ENTRIES, &entries)]);
+        assert!(HpakHeader::decode(&bytes[..]).is_err());
+
+        // truncated section
+        let mut bytes = encode_header(0, &[(section::ENTRIES, &entries)]);
+        bytes.pop();
+        assert!(HpakHeader::decode(&bytes[..]).is_err());
     }
 
     #[rstest]
-    #[case(CompressionMethod::None, 16, 32, 64, 128)]
-    #[case(CompressionMethod::Zlib, 32, 64, 128, 256)]
+    #[case(CompressionMethod::None, 16, 32, 64, 128, 0, vec![])]
+    #[case(CompressionMethod::Zlib, 32, 64, 128, 256, 0, vec![])]
+    #[case(CompressionMethod::Zstd(-5), 64, 128, 256, 512, 0, vec![])]
+    #[case(CompressionMethod::Lz4, 64, 128, 256, 512, 1024, vec![200, 312])]
     fn it_encode_decode_file_entry(
         #[case] method: CompressionMethod,
         #[case] hash: u64,
         #[case] meta_offset: u64,
         #[case] meta_size: u64,
         #[case] data_size: u64,
+        #[case] block_size: u64,
+        #[case] blocks: Vec<u64>,
     ) {
         let entry = HpakFileEntry {
             hash,
+            path: PathBuf::from(format!("dir/{hash}.bin")),
             compression_method: method,
             meta_offset,
             meta_size,
             data_size,
+            uncompressed_size: data_size * 2,
+            block_size,
+            blocks,
+            meta_checksum: hash ^ meta_size,
+            data_checksum: hash ^ data_size,
+            nonce: (block_size > 0).then_some([hash as u8; NONCE_SIZE]),
+            legacy: false,
         };
         let decoded = encode_decode(entry.clone());
 
         assert_eq!(entry.hash, decoded.hash);
+        assert_eq!(entry.path, decoded.path);
         assert_eq!(entry.compression_method, decoded.compression_method);
         assert_eq!(entry.meta_offset, decoded.meta_offset);
         assert_eq!(entry.meta_size, decoded.meta_size);
         assert_eq!(entry.data_size, decoded.data_size);
+        assert_eq!(entry.uncompressed_size, decoded.uncompressed_size);
+        assert_eq!(entry.block_size, decoded.block_size);
+        assert_eq!(entry.blocks, decoded.blocks);
+        assert_eq!(entry.meta_checksum, decoded.meta_checksum);
+        assert_eq!(entry.data_checksum, decoded.data_checksum);
+        assert_eq!(entry.nonce, decoded.nonce);
     }
 
     #[rstest]
     #[case(CompressionMethod::None)]
     #[case(CompressionMethod::Zlib)]
+    #[case(CompressionMethod::Zstd(0))]
+    #[case(CompressionMethod::Zstd(22))]
+    #[case(CompressionMethod::Lz4)]
     fn it_encode_decode_compression_method(#[case] method: CompressionMethod) {
         assert_eq!(method, encode_decode(method));
     }
@@ -371,24 +972,48 @@ mod tests {
         vec![
             HpakFileEntry {
                 hash: 0,
+                path: PathBuf::from("file_0"),
                 compression_method: CompressionMethod::None,
                 meta_offset: 0,
                 meta_size: 0,
                 data_size: 0,
+                uncompressed_size: 0,
+                block_size: 0,
+                blocks: Vec::new(),
+                meta_checksum: 0,
+                data_checksum: 0,
+                nonce: None,
+                legacy: false,
             },
             HpakFileEntry {
                 hash: 1,
+                path: PathBuf::from("file_1"),
                 compression_method: CompressionMethod::None,
                 meta_offset: 42,
                 meta_size: 100,
                 data_size: 100,
+                uncompressed_size: 100,
+                block_size: 0,
+                blocks: Vec::new(),
+                meta_checksum: 0,
+                data_checksum: 0,
+                nonce: None,
+                legacy: false,
             },
             HpakFileEntry {
                 hash: 2,
+                path: PathBuf::from("file_2"),
                 compression_method: CompressionMethod::None,
                 meta_offset: 100,
                 meta_size: 200,
                 data_size: 400,
+                uncompressed_size: 400,
+                block_size: 0,
+                blocks: Vec::new(),
+                meta_checksum: 0,
+                data_checksum: 0,
+                nonce: None,
+                legacy: false,
             },
         ],
         vec![
@@ -410,24 +1035,48 @@ mod tests {
         vec![
             HpakFileEntry {
                 hash: 128,
+                path: PathBuf::from("file_128"),
                 compression_method: CompressionMethod::Zlib,
                 meta_offset: 0,
                 meta_size: 0,
                 data_size: 0,
+                uncompressed_size: 0,
+                block_size: 0,
+                blocks: Vec::new(),
+                meta_checksum: 0,
+                data_checksum: 0,
+                nonce: None,
+                legacy: false,
             },
             HpakFileEntry {
                 hash: 256,
+                path: PathBuf::from("file_256"),
                 compression_method: CompressionMethod::None,
                 meta_offset: 42,
                 meta_size: 100,
                 data_size: 100,
+                uncompressed_size: 100,
+                block_size: 0,
+                blocks: Vec::new(),
+                meta_checksum: 0,
+                data_checksum: 0,
+                nonce: None,
+                legacy: false,
             },
             HpakFileEntry {
                 hash: 512,
+                path: PathBuf::from("file_512"),
                 compression_method: CompressionMethod::Zlib,
                 meta_offset: 100,
                 meta_size: 200,
                 data_size: u64::MAX,
+                uncompressed_size: u64::MAX,
+                block_size: 0,
+                blocks: Vec::new(),
+                meta_checksum: 0,
+                data_checksum: 0,
+                nonce: None,
+                legacy: false,
             },
         ],
         vec![
@@ -452,6 +1101,7 @@ mod tests {
         let mut entries = HpakEntries {
             directories: HashTable::new(),
             files: HashTable::new(),
+            tombstones: HashTable::new(),
         };
 
         for entry in files {
@@ -485,6 +1135,57 @@ mod tests {
         }
     }
 
+    fn file_entry(hash: u64, path: &str) -> HpakFileEntry {
+        HpakFileEntry {
+            hash,
+            path: PathBuf::from(path),
+            compression_method: CompressionMethod::None,
+            meta_offset: 0,
+            meta_size: 0,
+            data_size: 0,
+            uncompressed_size: 0,
+            block_size: 0,
+            blocks: Vec::new(),
+            meta_checksum: 0,
+            data_checksum: 0,
+            nonce: None,
+            legacy: false,
+        }
+    }
+
+    #[test]
+    fn it_reject_hash_collisions() {
+        let mut entries = HpakEntries {
+            directories: HashTable::new(),
+            files: HashTable::new(),
+            tombstones: HashTable::new(),
+        };
+
+        let hash = hash_path("a.txt");
+        entries.insert_file(file_entry(hash, "a.txt")).unwrap();
+
+        assert!(matches!(
+            entries.insert_file(file_entry(hash, "b.txt")),
+            Err(crate::Error::HashCollision(a, b)) if a == Path::new("a.txt") && b == Path::new("b.txt")
+        ));
+    }
+
+    #[test]
+    fn it_confirm_path_on_lookup() {
+        let mut entries = HpakEntries {
+            directories: HashTable::new(),
+            files: HashTable::new(),
+            tombstones: HashTable::new(),
+        };
+
+        // simulate a collision: "b.txt" stored under the hash of "a.txt"
+        entries
+            .insert_file(file_entry(hash_path("a.txt"), "b.txt"))
+            .unwrap();
+
+        assert!(entries.find_file(Path::new("a.txt")).is_none());
+    }
+
     #[test]
     #[should_panic]
     fn if_fails_to_decode_invalid_compression_method() {
@@ -495,15 +1196,20 @@ mod tests {
         let _ = CompressionMethod::decode(&mut bytes.as_slice()).unwrap();
     }
 
-    #[test]
-    fn it_compress_decompress() {
+    #[rstest]
+    #[case(ZlibEncoder::default())]
+    #[case(ZlibEncoder::Zopfli(0))]
+    #[case(ZlibEncoder::Flate2(0))]
+    #[case(ZlibEncoder::Flate2(6))]
+    #[case(ZlibEncoder::Flate2(42))]
+    fn it_compress_decompress(#[case] encoder: ZlibEncoder) {
         use flate2::read::ZlibDecoder;
 
         let bytes = Vec::from(b"Hello World!");
         let mut encoded = Vec::new();
 
         let size = CompressionMethod::Zlib
-            .compress(std::io::Cursor::new(&bytes), &mut encoded)
+            .compress(encoder, std::io::Cursor::new(&bytes), &mut encoded)
             .unwrap();
 
         assert_eq!(encoded.len() as u64, size);
@@ -516,4 +1222,48 @@ mod tests {
 
         assert_eq!(bytes, decoded);
     }
+
+    #[test]
+    fn it_compress_decompress_zstd() {
+        let bytes = Vec::from(b"Hello World!");
+        let mut encoded = Vec::new();
+
+        let size = CompressionMethod::Zstd(19)
+            .compress(
+                ZlibEncoder::default(),
+                std::io::Cursor::new(&bytes),
+                &mut encoded,
+            )
+            .unwrap();
+
+        assert_eq!(encoded.len() as u64, size);
+
+        let decoded = zstd::stream::decode_all(std::io::Cursor::new(encoded)).unwrap();
+
+        assert_eq!(bytes, decoded);
+    }
+
+    #[test]
+    fn it_compress_decompress_lz4() {
+        let bytes = Vec::from(b"Hello World!");
+        let mut encoded = Vec::new();
+
+        let size = CompressionMethod::Lz4
+            .compress(
+                ZlibEncoder::default(),
+                std::io::Cursor::new(&bytes),
+                &mut encoded,
+            )
+            .unwrap();
+
+        assert_eq!(encoded.len() as u64, size);
+
+        let mut decoded = Vec::new();
+
+        lz4_flex::frame::FrameDecoder::new(std::io::Cursor::new(encoded))
+            .read_to_end(&mut decoded)
+            .unwrap();
+
+        assert_eq!(bytes, decoded);
+    }
 }
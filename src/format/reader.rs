@@ -1,6 +1,9 @@
 use super::*;
 use crate::{Error, Result, encoding::*};
-use bevy::asset::io::{AssetReader, AssetReaderError, PathStream, Reader, SeekableReader};
+use bevy::asset::io::{
+    AssetReader, AssetReaderError, PathStream, Reader, STACK_FUTURE_SIZE, SeekableReader,
+    StackFuture,
+};
 use futures_io::{AsyncRead, AsyncSeek};
 use memmap2::Mmap;
 use std::mem::ManuallyDrop;
@@ -11,12 +14,17 @@ use std::{
     io::{Seek, SeekFrom},
     sync::Arc,
 };
+use xxhash_rust::xxh3::xxh3_64;
 
+#[cfg_attr(feature = "debug-impls", derive(Debug))]
 pub struct HpakReader {
     file: ManuallyDrop<File>,
     mmap: Arc<Mmap>,
-    meta_compression_method: CompressionMethod,
+    header: HpakHeader,
     entries: HpakEntries,
+    verify_checksums: bool,
+    /// Cipher of the entries, if encrypted.
+    cipher: Option<ArchiveCipher>,
 }
 
 impl Drop for HpakReader {
@@ -33,7 +41,7 @@ const _: () = {
 };
 
 #[cfg(windows)]
-fn open_archive(path: impl AsRef<Path>) -> Result<File> {
+pub(crate) fn open_archive(path: impl AsRef<Path>) -> Result<File> {
     use std::os::windows::fs::OpenOptionsExt;
 
     Ok(OpenOptions::new()
@@ -44,7 +52,7 @@ fn open_archive(path: impl AsRef<Path>) -> Result<File> {
 }
 
 #[cfg(unix)]
-fn open_archive(path: impl AsRef<Path>) -> Result<File> {
+pub(crate) fn open_archive(path: impl AsRef<Path>) -> Result<File> {
     use std::os::unix::fs::OpenOptionsExt;
 
     Ok(OpenOptions::new()
@@ -58,55 +66,334 @@ impl HpakReader {
     ///
     /// This opens the file and reads the header and entry table into memory.
     /// The actual asset data remains on disk and is accessed via memory mapping.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be opened, is not a valid HPAK archive
+    /// or if its entry table doesn't match the checksum stored in the header.
+    /// Returns [`Error::InvalidKey`] if the archive is encrypted, see
+    /// `HpakReader::new_encrypted`.
     pub fn new(path: impl AsRef<Path>) -> Result<Self> {
-        let mut file = open_archive(path)?;
+        Self::open(path, |_| None)
+    }
+
+    /// Create a new HPAK reader for the archive at the specified path, decrypting
+    /// its entries with `key`.
+    ///
+    /// Archives that are not encrypted are read as with [`HpakReader::new`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidKey`] if the archive was encrypted with another key,
+    /// see [`HpakReader::new`] for the other errors.
+    #[cfg(feature = "encryption")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "encryption")))]
+    pub fn new_encrypted(path: impl AsRef<Path>, key: &[u8; 32]) -> Result<Self> {
+        Self::open(path, |_| Some(*key))
+    }
 
-        // read header
-        file.seek(SeekFrom::Start(0))?;
-        let header = HpakHeader::decode(&mut file)?;
+    /// Create a new HPAK reader for the archive at the specified path, decrypting
+    /// its entries with the key returned by `key_provider`.
+    ///
+    /// `key_provider` is called with the path of the archive, only if it is encrypted.
+    /// This allows fetching the key lazily or using a different key per archive.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidKey`] if the archive is encrypted and `key_provider`
+    /// returns `None` or another key, see [`HpakReader::new`] for the other errors.
+    #[cfg(feature = "encryption")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "encryption")))]
+    pub fn new_with_key_provider(
+        path: impl AsRef<Path>,
+        key_provider: impl FnOnce(&Path) -> Option<[u8; 32]>,
+    ) -> Result<Self> {
+        Self::open(path, key_provider)
+    }
 
-        // jump to the entries table
-        file.seek(SeekFrom::Start(header.entries_offset))?;
-        let entries = HpakEntries::decode(&mut file)?;
+    fn open(
+        path: impl AsRef<Path>,
+        key_provider: impl FnOnce(&Path) -> Option<[u8; 32]>,
+    ) -> Result<Self> {
+        let path = path.as_ref();
 
+        Self::open_with(path, |header| {
+            let key = header.key_check.and_then(|_| key_provider(path));
+            ArchiveCipher::for_archive(key.as_ref(), header.key_check.as_ref())
+        })
+    }
+
+    /// Open the archive at `path` without checking its encryption key, the entries
+    /// of encrypted archives can then only be accessed as raw bytes.
+    ///
+    /// `cipher` is only used if it matches the key of the archive, to decrypt its
+    /// entry table.
+    #[cfg(feature = "writer")]
+    pub(crate) fn open_unchecked(
+        path: impl AsRef<Path>,
+        cipher: Option<&ArchiveCipher>,
+    ) -> Result<Self> {
+        Self::open_with(path, |header| {
+            Ok(cipher
+                .zip(header.key_check.as_ref())
+                .filter(|(cipher, key_check)| cipher.check_key(key_check))
+                .map(|(cipher, _)| cipher.clone()))
+        })
+    }
+
+    /// Open the archive at `path` with the cipher returned by `cipher` for its header.
+    fn open_with(
+        path: impl AsRef<Path>,
+        cipher: impl FnOnce(&HpakHeader) -> Result<Option<ArchiveCipher>>,
+    ) -> Result<Self> {
+        let file = open_archive(path)?;
         let mmap = unsafe { Mmap::map(&file)? };
 
+        let (header, _) = HpakHeader::from_archive(&mmap)?;
+        let table = header.entries_table(&mmap)?;
+
+        if xxh3_64(table) != header.entries_checksum {
+            return Err(Error::EntriesChecksumMismatch);
+        }
+
+        let cipher = cipher(&header)?;
+
+        let entries = match (&header.entries_nonce, &cipher) {
+            (None, _) => HpakEntries::decode_version(table, header.version)?,
+            (Some(nonce), Some(cipher)) => {
+                HpakEntries::decode(&cipher.decrypt_entries(nonce, table)?[..])?
+            }
+            (Some(_), None) => return Err(Error::InvalidKey),
+        };
+
         Ok(Self {
             file: ManuallyDrop::new(file),
             mmap: Arc::new(mmap),
-            meta_compression_method: header.meta_compression_method,
+            header,
             entries,
+            verify_checksums: false,
+            cipher,
         })
     }
 
+    /// Verify the integrity of the whole archive.
+    ///
+    /// See [`verify_archive`] for the list of performed checks. This decompresses
+    /// every entry, so it may take a while on large archives.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the archive's header is no longer valid.
+    pub fn verify(&self) -> Result<VerifyReport> {
+        verify_mmap(&self.mmap, self.cipher.as_ref())
+    }
+
+    /// Enable or disable checksum verification of the entries read from this archive.
+    ///
+    /// When enabled, the uncompressed metadata and data of each entry are hashed as
+    /// they are read and compared against the checksums stored in the archive once the
+    /// end of the entry is reached, reading it fails with an [`std::io::Error`] of kind
+    /// `InvalidData` wrapping [`Error::ChecksumMismatch`] on mismatch.
+    ///
+    /// The first seek of an entry hashes the rest of it before seeking, so it fails
+    /// the same way, at the cost of decompressing it one more time. Bytes are returned
+    /// as they are read, the reads that stop before the end of an entry without
+    /// seeking are not verified. `false` by default.
+    pub fn with_checksum_verification(mut self, verify: bool) -> Self {
+        self.verify_checksums = verify;
+        self
+    }
+
+    /// Returns a reader over the metadata of the file at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::EntryNotFound`] if there is no file at `path` and
+    /// [`Error::MetaNotFound`] if the file was packed without metadata.
     pub fn read_meta(&self, path: &Path) -> Result<HpakEntryReader> {
         let entry = self.get_entry(path)?;
 
-        Ok(HpakEntryReader::new(
+        if entry.meta_size == 0 {
+            return Err(Error::MetaNotFound(path.to_path_buf()));
+        }
+
+        let reader = HpakEntryReader::for_meta(
             self.mmap.clone(),
-            entry.meta_offset,
-            entry.meta_size,
-            self.meta_compression_method,
-        ))
+            entry,
+            self.header.meta_compression_method,
+            self.entry_cipher(entry),
+        )?;
+
+        Ok(self.verified(reader, entry, entry.meta_checksum))
     }
 
     pub fn read_data(&self, path: &Path) -> Result<HpakEntryReader> {
         let entry = self.get_entry(path)?;
 
-        Ok(HpakEntryReader::new(
-            self.mmap.clone(),
-            entry.meta_offset + entry.meta_size,
-            entry.data_size,
-            entry.compression_method,
-        ))
+        let reader = HpakEntryReader::for_data(self.mmap.clone(), entry, self.entry_cipher(entry))?;
+
+        Ok(self.verified(reader, entry, entry.data_checksum))
     }
 
-    fn get_entry(&self, path: &Path) -> Result<&HpakFileEntry> {
-        let hash = hash_path(path);
+    /// Returns the version of the format the archive was written with.
+    ///
+    /// Archives written with a previous version store neither the checksums nor the
+    /// uncompressed size of their entries, see `writer::migrate_archive`.
+    pub fn version(&self) -> u32 {
+        self.header.version
+    }
+
+    /// Returns the bytes stored in the header with `HpakWriter::with_user_data`, if any.
+    pub fn user_data(&self) -> Option<&[u8]> {
+        self.header.user_data.as_deref()
+    }
+
+    /// Returns `true` if the archive is signed.
+    pub fn is_signed(&self) -> bool {
+        self.header.signature.is_some()
+    }
+
+    /// Check that the header and entry table of the archive, which holds the
+    /// checksums of every entry, are signed with the secret key of `public_key`.
+    ///
+    /// This doesn't read the entries, combine it with
+    /// [`HpakReader::with_checksum_verification`] or [`HpakReader::verify`] to detect
+    /// tampered entries too.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidSignature`] if the archive is not signed or if its
+    /// signature doesn't match `public_key`.
+    #[cfg(feature = "signing")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "signing")))]
+    pub fn verify_signature(&self, public_key: &[u8; 32]) -> Result<()> {
+        let (_, header_size) = HpakHeader::from_archive(&self.mmap)?;
+
+        let header = &self.mmap[..header_size];
+        let table = self.header.entries_table(&self.mmap)?;
+
+        match &self.header.signature {
+            Some(signature) if check_signature(public_key, header, table, signature) => Ok(()),
+            _ => Err(Error::InvalidSignature),
+        }
+    }
+
+    /// Returns `true` if the entries of the archive are encrypted.
+    pub fn is_encrypted(&self) -> bool {
+        self.header.key_check.is_some()
+    }
+
+    /// Returns the compression method used for the metadata of every entry.
+    pub fn meta_compression_method(&self) -> CompressionMethod {
+        self.header.meta_compression_method
+    }
+
+    /// Returns the size of the data stored at `path` once decompressed.
+    ///
+    /// Compressed files of archives written with a previous format version don't
+    /// store it, it is then computed by decompressing the file.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::EntryNotFound`] if there is no file at `path`, or an error if
+    /// the file of an archive written with a previous format version fails to decompress.
+    pub fn uncompressed_size(&self, path: &Path) -> Result<u64> {
+        self.entry_uncompressed_size(self.get_entry(path)?)
+    }
 
+    /// Returns the total size of the data stored in the archive once decompressed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a compressed file of an archive written with a previous
+    /// format version fails to decompress, see [`HpakReader::uncompressed_size`].
+    pub fn total_uncompressed_size(&self) -> Result<u64> {
         self.entries
             .files
+            .iter()
+            .map(|entry| self.entry_uncompressed_size(entry))
+            .sum()
+    }
+
+    /// Returns the uncompressed size of the data of `entry`, decompressing legacy entries.
+    fn entry_uncompressed_size(&self, entry: &HpakFileEntry) -> Result<u64> {
+        match entry.compression_method {
+            CompressionMethod::None => Ok(entry.uncompressed_size),
+            _ if entry.legacy => legacy::uncompressed_size(&self.mmap, entry),
+            _ => Ok(entry.uncompressed_size),
+        }
+    }
+
+    /// Returns the key-check value of the encryption key, if the archive is encrypted.
+    #[cfg(feature = "writer")]
+    pub(crate) fn key_check(&self) -> Option<&[u8; KEY_CHECK_SIZE]> {
+        self.header.key_check.as_ref()
+    }
+
+    /// Returns the cipher of `entry`, if encrypted.
+    fn entry_cipher(&self, entry: &HpakFileEntry) -> Option<EntryCipher> {
+        self.cipher.as_ref().and_then(|cipher| cipher.entry(entry))
+    }
+
+    /// Returns the raw bytes stored at `offset` in the archive, if in bounds.
+    pub(crate) fn raw_bytes(&self, offset: u64, size: u64) -> Option<&[u8]> {
+        let start = usize::try_from(offset).ok()?;
+        let end = start.checked_add(usize::try_from(size).ok()?)?;
+
+        self.mmap.get(start..end)
+    }
+
+    /// Returns an iterator over every file stored in the archive, in no particular order.
+    pub fn iter_files(&self) -> impl Iterator<Item = &HpakFileEntry> {
+        self.entries.files.iter()
+    }
+
+    /// Returns an iterator over the paths of the files deleted by this archive.
+    ///
+    /// Tombstones hide the files of the archives mounted below this one in a
+    /// [`HpakLayeredReader`](crate::HpakLayeredReader).
+    pub fn iter_tombstones(&self) -> impl Iterator<Item = &Path> {
+        self.entries.tombstones.iter().map(PathBuf::as_path)
+    }
+
+    /// Returns `true` if this archive deletes the file at `path`.
+    pub(crate) fn is_tombstone(&self, path: &Path) -> bool {
+        self.entries.is_tombstone(path)
+    }
+
+    /// Returns the paths of the entries directly in the directory at `path`, if any.
+    pub(crate) fn directory_entries(&self, path: &Path) -> Option<&[PathBuf]> {
+        let hash = hash_path(path);
+
+        self.entries
+            .directories
             .find(hash, |entry| entry.hash == hash)
+            .map(|entry| entry.entries.as_slice())
+    }
+
+    /// Returns `reader`, verifying that it matches the `checksum` of `entry` as it is
+    /// read if verification is enabled.
+    fn verified(
+        &self,
+        mut reader: HpakEntryReader,
+        entry: &HpakFileEntry,
+        checksum: u64,
+    ) -> HpakEntryReader {
+        // legacy entries have no checksum
+        if self.verify_checksums && !entry.legacy {
+            reader.verifier = Some(Verifier {
+                hasher: Xxh3::new(),
+                checksum,
+                path: entry.path.clone(),
+            });
+        }
+
+        reader
+    }
+
+    pub(crate) fn get_entry(&self, path: &Path) -> Result<&HpakFileEntry> {
+        self.entries
+            .find_file(path)
             .ok_or_else(|| Error::EntryNotFound(path.to_path_buf()))
     }
 }
@@ -139,15 +426,25 @@ impl AssetReader for HpakReader {
         let entry = self.get_entry(path)?;
 
         if entry.meta_size == 0 {
-            return Ok(Vec::new());
+            return Err(AssetReaderError::NotFound(path.to_path_buf()));
         }
 
-        let start = entry.meta_offset as usize;
-        let end = start + entry.meta_size as usize;
+        match self.header.meta_compression_method {
+            CompressionMethod::None if !entry.is_encrypted() => {
+                let meta_bytes = self
+                    .raw_bytes(entry.meta_offset, entry.meta_size)
+                    .ok_or(Error::InvalidFileFormat)?;
+
+                if self.verify_checksums
+                    && !entry.legacy
+                    && xxh3_64(meta_bytes) != entry.meta_checksum
+                {
+                    return Err(Error::ChecksumMismatch(path.to_path_buf()).into());
+                }
 
-        match entry.compression_method {
-            CompressionMethod::None => Ok(self.mmap[start..end].to_vec()),
-            CompressionMethod::Zlib => {
+                Ok(meta_bytes.to_vec())
+            }
+            _ => {
                 let mut meta_reader = self.read_meta(path)?;
                 let mut meta_bytes = Vec::new();
                 meta_reader.read_to_end(&mut meta_bytes).await?;
@@ -160,14 +457,8 @@ impl AssetReader for HpakReader {
         &'a self,
         path: &'a Path,
     ) -> std::result::Result<Box<PathStream>, AssetReaderError> {
-        let hash = hash_path(path);
-
-        match self
-            .entries
-            .directories
-            .find(hash, |entry| entry.hash == hash)
-        {
-            Some(entry) => Ok(Box::new(DirStream(entry.entries.clone()))),
+        match self.directory_entries(path) {
+            Some(entries) => Ok(Box::new(DirStream(entries.to_vec()))),
             None => Err(AssetReaderError::NotFound(path.to_path_buf())),
         }
     }
@@ -176,18 +467,21 @@ impl AssetReader for HpakReader {
         &'a self,
         path: &'a Path,
     ) -> std::result::Result<bool, bevy::asset::io::AssetReaderError> {
-        let path = hash_path(path);
-
-        Ok(self
-            .entries
-            .directories
-            .find(path, |entry| entry.hash == path)
-            .is_some())
+        Ok(self.directory_entries(path).is_some())
     }
 }
 
 pub struct HpakEntryReader {
     state: ReaderState,
+    /// Checksum the entry is verified against while read, until the end is reached.
+    verifier: Option<Verifier>,
+}
+
+/// Hasher of the bytes of an entry read so far, from its start.
+struct Verifier {
+    hasher: Xxh3,
+    checksum: u64,
+    path: PathBuf,
 }
 
 impl HpakEntryReader {
@@ -197,40 +491,588 @@ impl HpakEntryReader {
         size: u64,
         compression_method: CompressionMethod,
     ) -> Self {
-        let slice = MmapSliceReader::new(source.clone(), offset as usize, size as usize);
+        Self::from_slice(
+            SliceReader::new(source, offset as usize, size as usize),
+            compression_method,
+        )
+    }
 
+    fn from_slice(slice: SliceReader, compression_method: CompressionMethod) -> Self {
         let state = match compression_method {
             CompressionMethod::None => ReaderState::Uncompressed(slice),
-            CompressionMethod::Zlib => ReaderState::Compressed {
-                cursor: 0,
-                decoder: Box::new(flate2::read::ZlibDecoder::new_with_buf(
-                    slice,
-                    vec![0u8; 4 * 1024],
-                )) as Box<dyn Read + Send + Sync>,
-            },
+            method => ReaderState::Compressed(CompressedReader::new(slice, method)),
+        };
+
+        Self {
+            state,
+            verifier: None,
+        }
+    }
+
+    /// Create a reader over the bytes of `segment` of an encrypted entry, decrypting them upfront.
+    fn decrypted(
+        source: &Mmap,
+        offset: u64,
+        size: u64,
+        compression_method: CompressionMethod,
+        cipher: &EntryCipher,
+        segment: u32,
+    ) -> Result<Self> {
+        let end = offset.checked_add(size).ok_or(Error::InvalidFileFormat)?;
+        let bytes = source
+            .get(offset as usize..end as usize)
+            .ok_or(Error::InvalidFileFormat)?;
+        let bytes = cipher.decrypt(segment, bytes)?;
+        let len = bytes.len();
+
+        Ok(Self::from_slice(
+            SliceReader::new(Arc::new(bytes), 0, len),
+            compression_method,
+        ))
+    }
+
+    /// Create a reader over the metadata of `entry`, compressed with `compression_method`.
+    pub(crate) fn for_meta(
+        source: Arc<Mmap>,
+        entry: &HpakFileEntry,
+        compression_method: CompressionMethod,
+        cipher: Option<EntryCipher>,
+    ) -> Result<Self> {
+        match cipher {
+            Some(cipher) => Self::decrypted(
+                &source,
+                entry.meta_offset,
+                entry.meta_size,
+                compression_method,
+                &cipher,
+                META_SEGMENT,
+            ),
+            None => Ok(Self::new(
+                source,
+                entry.meta_offset,
+                entry.meta_size,
+                compression_method,
+            )),
+        }
+    }
+
+    /// Create a reader over the data of `entry`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::DecryptionFailed`] if the data of an encrypted entry stored
+    /// as a single stream cannot be decrypted, blocks are decrypted as they are read.
+    /// Returns [`Error::InvalidFileFormat`] if the block table of the entry doesn't
+    /// match its data.
+    pub(crate) fn for_data(
+        source: Arc<Mmap>,
+        entry: &HpakFileEntry,
+        cipher: Option<EntryCipher>,
+    ) -> Result<Self> {
+        let offset = entry
+            .meta_offset
+            .checked_add(entry.meta_size)
+            .ok_or(Error::InvalidFileFormat)?;
+
+        let mut reader = match cipher {
+            _ if entry.block_size > 0 => {
+                let chunked = ChunkedReader::new(
+                    source,
+                    offset,
+                    entry.compression_method,
+                    entry.block_size,
+                    &entry.blocks,
+                    cipher,
+                )?;
+
+                if Some(chunked.end()) != offset.checked_add(entry.data_size) {
+                    return Err(Error::InvalidFileFormat);
+                }
+
+                Self {
+                    state: ReaderState::Chunked(chunked),
+                    verifier: None,
+                }
+            }
+            Some(cipher) => Self::decrypted(
+                &source,
+                offset,
+                entry.data_size,
+                entry.compression_method,
+                &cipher,
+                data_segment(0).ok_or(Error::InvalidFileFormat)?,
+            )?,
+            None => Self::new(source, offset, entry.data_size, entry.compression_method),
         };
 
-        Self { state }
+        // the uncompressed size of legacy entries is only known once decompressed
+        match &mut reader.state {
+            _ if entry.legacy => {}
+            ReaderState::Uncompressed(_) => {}
+            ReaderState::Compressed(compressed) => compressed.len = Some(entry.uncompressed_size),
+            ReaderState::Chunked(chunked) => chunked.len = Some(entry.uncompressed_size),
+        }
+
+        Ok(reader)
+    }
+
+    /// Returns the size of the entry once decompressed, if known without decompressing it.
+    ///
+    /// This is always known for readers returned by [`HpakReader::read_data`], except
+    /// for compressed files of archives written with a previous format version.
+    pub fn uncompressed_size(&self) -> Option<u64> {
+        match &self.state {
+            ReaderState::Uncompressed(reader) => Some(reader.len as u64),
+            ReaderState::Compressed(reader) => reader.len,
+            ReaderState::Chunked(reader) => reader.len,
+        }
+    }
+
+    /// Read from the entry, hashing the bytes read and checking the digest at the end
+    /// of the entry if verified.
+    fn verified_read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let Some(verifier) = &mut self.verifier else {
+            return self.state.read(buf);
+        };
+
+        let n = self.state.read(buf)?;
+        verifier.hasher.update(&buf[..n]);
+
+        if n == 0 && !buf.is_empty() {
+            if verifier.hasher.digest() != verifier.checksum {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    Error::ChecksumMismatch(verifier.path.clone()),
+                ));
+            }
+
+            self.verifier = None;
+        }
+
+        Ok(n)
+    }
+
+    /// Seek in the entry, after hashing the rest of it if verified.
+    fn verified_seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let moves = match pos {
+            SeekFrom::Current(offset) => offset != 0,
+            SeekFrom::Start(offset) => offset != self.state.position(),
+            SeekFrom::End(_) => true,
+        };
+
+        // the bytes skipped or read again are never hashed in order, hash the rest first
+        if moves && self.verifier.is_some() {
+            let mut buffer = [0u8; 8 * 1024];
+
+            while self.verifier.is_some() {
+                self.verified_read(&mut buffer)?;
+            }
+        }
+
+        self.state.seek(pos)
+    }
+
+    /// Create a reader over data stored as independently compressed blocks.
+    ///
+    /// `blocks` holds the compressed size of each block, each one decompressing
+    /// to `block_size` bytes except for the last one. Seeking only decompresses
+    /// the block containing the target position.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidFileFormat`] if `block_size` is zero or if the blocks
+    /// extend past the end of `source`.
+    #[cfg(test)]
+    fn new_chunked(
+        source: Arc<Mmap>,
+        offset: u64,
+        compression_method: CompressionMethod,
+        block_size: u64,
+        blocks: &[u64],
+    ) -> Result<Self> {
+        Ok(Self {
+            state: ReaderState::Chunked(ChunkedReader::new(
+                source,
+                offset,
+                compression_method,
+                block_size,
+                blocks,
+                None,
+            )?),
+            verifier: None,
+        })
     }
 }
 
 enum ReaderState {
-    Uncompressed(MmapSliceReader),
-    Compressed {
-        cursor: u64,
-        decoder: Box<dyn Read + Send + Sync + 'static>,
-    },
+    Uncompressed(SliceReader),
+    Compressed(CompressedReader),
+    Chunked(ChunkedReader),
+}
+
+impl ReaderState {
+    /// Position in the uncompressed stream.
+    fn position(&self) -> u64 {
+        match self {
+            ReaderState::Uncompressed(reader) => reader.pos as u64,
+            ReaderState::Compressed(reader) => reader.cursor,
+            ReaderState::Chunked(reader) => reader.cursor,
+        }
+    }
+
+    /// Number of uncompressed bytes left to read, capped to what the compressed
+    /// bytes can plausibly decompress to since the stored size may be corrupted.
+    fn remaining_hint(&self) -> usize {
+        let (len, compressed) = match self {
+            ReaderState::Uncompressed(reader) => {
+                let available = (*reader.source)
+                    .as_ref()
+                    .len()
+                    .saturating_sub(reader.offset);
+                return reader.len.min(available).saturating_sub(reader.pos);
+            }
+            ReaderState::Compressed(reader) => (reader.len, reader.source.len as u64),
+            ReaderState::Chunked(reader) => (reader.len, reader.end() - reader.offsets[0]),
+        };
+
+        let len = len
+            .unwrap_or(0)
+            .min(compressed.saturating_mul(MAX_RESERVED_RATIO));
+
+        len.saturating_sub(self.position()) as usize
+    }
+}
+
+impl Read for ReaderState {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            ReaderState::Uncompressed(reader) => reader.read(buf),
+            ReaderState::Compressed(reader) => reader.read(buf),
+            ReaderState::Chunked(reader) => reader.read(buf),
+        }
+    }
+}
+
+impl Seek for ReaderState {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        match self {
+            ReaderState::Uncompressed(reader) => reader.seek(pos),
+            ReaderState::Compressed(reader) => reader.seek(pos),
+            ReaderState::Chunked(reader) => reader.seek(pos),
+        }
+    }
+}
+
+/// Maximum ratio between the uncompressed and compressed size of an entry reserved
+/// upfront by [`Reader::read_to_end`].
+const MAX_RESERVED_RATIO: u64 = 32;
+
+fn decoder_for(
+    slice: SliceReader,
+    compression_method: CompressionMethod,
+) -> Box<dyn Read + Send + Sync + 'static> {
+    match compression_method {
+        CompressionMethod::None => Box::new(slice),
+        CompressionMethod::Zlib => Box::new(flate2::read::ZlibDecoder::new_with_buf(
+            slice,
+            vec![0u8; 4 * 1024],
+        )),
+        CompressionMethod::Zstd(_) => match zstd::stream::read::Decoder::new(slice) {
+            Ok(decoder) => Box::new(decoder),
+            Err(err) => Box::new(FailedDecoder(Some(err))),
+        },
+        CompressionMethod::Lz4 => Box::new(lz4_flex::frame::FrameDecoder::new(slice)),
+    }
+}
+
+/// Decoder whose initialization failed, reporting the error on first read.
+struct FailedDecoder(Option<std::io::Error>);
+
+impl Read for FailedDecoder {
+    fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+        match self.0.take() {
+            Some(err) => Err(err),
+            None => Ok(0),
+        }
+    }
+}
+
+/// Streaming decoder over a compressed entry.
+///
+/// Compressed streams cannot be seeked directly: forward seeks decompress and
+/// discard the skipped bytes, while backward seeks restart decompression from
+/// the beginning of the entry.
+struct CompressedReader {
+    /// Untouched slice over the compressed bytes, used to restart decompression.
+    source: SliceReader,
+    compression_method: CompressionMethod,
+    decoder: Box<dyn Read + Send + Sync + 'static>,
+    /// Position in the uncompressed stream.
+    cursor: u64,
+    /// Uncompressed length, known once the end of the stream has been reached.
+    len: Option<u64>,
+}
+
+impl CompressedReader {
+    fn new(source: SliceReader, compression_method: CompressionMethod) -> Self {
+        Self {
+            decoder: decoder_for(source.clone(), compression_method),
+            source,
+            compression_method,
+            cursor: 0,
+            len: None,
+        }
+    }
+
+    fn restart(&mut self) {
+        self.decoder = decoder_for(self.source.clone(), self.compression_method);
+        self.cursor = 0;
+    }
+
+    /// Decompress and discard up to `count` bytes, returning the new position.
+    fn skip(&mut self, mut count: u64) -> std::io::Result<u64> {
+        let mut buffer = [0u8; 4096];
+
+        while count > 0 {
+            match self.read(&mut buffer[..count.min(4096) as usize]) {
+                Ok(0) => break,
+                Ok(n) => count -= n as u64,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(self.cursor)
+    }
+
+    /// Returns the uncompressed length, decompressing the rest of the stream if unknown.
+    fn len(&mut self) -> std::io::Result<u64> {
+        if let Some(len) = self.len {
+            return Ok(len);
+        }
+
+        self.skip(u64::MAX)
+    }
+}
+
+impl Read for CompressedReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.decoder.read(buf)?;
+
+        if n == 0 && !buf.is_empty() {
+            self.len = Some(self.cursor);
+        }
+
+        self.cursor += n as u64;
+        Ok(n)
+    }
 }
 
-struct MmapSliceReader {
+impl Seek for CompressedReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => offset,
+            SeekFrom::Current(offset) => seek_offset(self.cursor, offset)?,
+            SeekFrom::End(offset) => seek_offset(self.len()?, offset)?,
+        };
+
+        if target < self.cursor {
+            self.restart();
+        }
+
+        self.skip(target - self.cursor)
+    }
+}
+
+/// Reader over data stored as a sequence of independently compressed blocks.
+struct ChunkedReader {
     source: Arc<Mmap>,
+    compression_method: CompressionMethod,
+    /// Uncompressed size of every block but the last one.
+    block_size: u64,
+    /// Absolute offset of each block in the archive, followed by the end offset of the last one.
+    offsets: Vec<u64>,
+    /// Decoder of the block containing `cursor`, opened lazily.
+    block: Option<(usize, CompressedReader)>,
+    /// Position in the uncompressed stream.
+    cursor: u64,
+    /// Uncompressed length, known once the last block has been decompressed.
+    len: Option<u64>,
+    /// Cipher decrypting each block as it is opened, if encrypted.
+    cipher: Option<EntryCipher>,
+}
+
+impl ChunkedReader {
+    /// Create a reader over the `blocks` stored from `offset` in `source`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidFileFormat`] if `block_size` is zero or if the blocks
+    /// extend past the end of `source`.
+    fn new(
+        source: Arc<Mmap>,
+        offset: u64,
+        compression_method: CompressionMethod,
+        block_size: u64,
+        blocks: &[u64],
+        cipher: Option<EntryCipher>,
+    ) -> Result<Self> {
+        if block_size == 0 {
+            return Err(Error::InvalidFileFormat);
+        }
+
+        let mut offsets = Vec::with_capacity(blocks.len() + 1);
+        offsets.push(offset);
+
+        for size in blocks {
+            let end = offsets[offsets.len() - 1]
+                .checked_add(*size)
+                .filter(|end| *end <= source.len() as u64)
+                .ok_or(Error::InvalidFileFormat)?;
+
+            offsets.push(end);
+        }
+
+        Ok(Self {
+            source,
+            compression_method,
+            block_size,
+            offsets,
+            block: None,
+            cursor: 0,
+            len: None,
+            cipher,
+        })
+    }
+
+    /// Returns the end offset of the last block in the archive.
+    fn end(&self) -> u64 {
+        self.offsets[self.offsets.len() - 1]
+    }
+
+    fn block_count(&self) -> usize {
+        self.offsets.len() - 1
+    }
+
+    fn open_block(&self, index: usize) -> std::io::Result<CompressedReader> {
+        let start = self.offsets[index] as usize;
+        let end = self.offsets[index + 1] as usize;
+
+        let slice = match &self.cipher {
+            Some(cipher) => {
+                let bytes = self.source.get(start..end).ok_or_else(|| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::UnexpectedEof,
+                        "data block is out of bounds",
+                    )
+                })?;
+                let bytes = data_segment(index)
+                    .ok_or(Error::InvalidFileFormat)
+                    .and_then(|segment| cipher.decrypt(segment, bytes))
+                    .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
+                let len = bytes.len();
+
+                SliceReader::new(Arc::new(bytes), 0, len)
+            }
+            None => SliceReader::new(self.source.clone(), start, end - start),
+        };
+
+        Ok(CompressedReader::new(slice, self.compression_method))
+    }
+
+    /// Returns the uncompressed length, decompressing the last block if unknown.
+    fn len(&mut self) -> std::io::Result<u64> {
+        if let Some(len) = self.len {
+            return Ok(len);
+        }
+
+        let len = match self.block_count() {
+            0 => 0,
+            count => {
+                let last = self.open_block(count - 1)?.len()?;
+                (count as u64 - 1) * self.block_size + last
+            }
+        };
+
+        self.len = Some(len);
+        Ok(len)
+    }
+}
+
+impl Read for ChunkedReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if unlikely(buf.is_empty()) {
+            return Ok(0);
+        }
+
+        let index = (self.cursor / self.block_size) as usize;
+        let is_last = index + 1 == self.block_count();
+
+        if index >= self.block_count() {
+            return Ok(0);
+        }
+
+        let reader = match &mut self.block {
+            Some((current, reader)) if *current == index => reader,
+            _ => {
+                let mut reader = self.open_block(index)?;
+                reader.seek(SeekFrom::Start(self.cursor % self.block_size))?;
+                &mut self.block.insert((index, reader)).1
+            }
+        };
+
+        match reader.read(buf)? {
+            0 if is_last => {
+                self.len = Some(index as u64 * self.block_size + reader.cursor);
+                Ok(0)
+            }
+            0 => Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "data block is shorter than the block size",
+            )),
+            n => {
+                self.cursor += n as u64;
+                Ok(n)
+            }
+        }
+    }
+}
+
+impl Seek for ChunkedReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => offset,
+            SeekFrom::Current(offset) => seek_offset(self.cursor, offset)?,
+            SeekFrom::End(offset) => seek_offset(self.len()?, offset)?,
+        };
+
+        let index = (target / self.block_size) as usize;
+
+        match &mut self.block {
+            Some((current, reader)) if *current == index => {
+                reader.seek(SeekFrom::Start(target % self.block_size))?;
+            }
+            _ => self.block = None,
+        }
+
+        self.cursor = target;
+        Ok(target)
+    }
+}
+
+/// Bytes read by a [`SliceReader`], either the mapped archive or decrypted bytes.
+type SliceSource = Arc<dyn AsRef<[u8]> + Send + Sync>;
+
+#[derive(Clone)]
+struct SliceReader {
+    source: SliceSource,
     offset: usize,
     len: usize,
     pos: usize,
 }
 
-impl MmapSliceReader {
-    fn new(source: Arc<Mmap>, offset: usize, len: usize) -> Self {
+impl SliceReader {
+    fn new(source: SliceSource, offset: usize, len: usize) -> Self {
         Self {
             source,
             offset,
@@ -238,20 +1080,16 @@ impl MmapSliceReader {
             pos: 0,
         }
     }
+}
 
-    fn seek_forward(&mut self, offset: u64) -> std::io::Result<u64> {
-        let new_pos = self.pos as u64 + offset;
-
-        if new_pos > self.len as u64 {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidInput,
-                "seek out of bounds",
-            ));
-        }
-
-        self.pos = new_pos as usize;
-        Ok(self.pos as u64)
-    }
+/// Apply a relative seek `offset` to `base`, rejecting negative or overflowing positions.
+fn seek_offset(base: u64, offset: i64) -> std::io::Result<u64> {
+    base.checked_add_signed(offset).ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "invalid seek to a negative or overflowing position",
+        )
+    })
 }
 
 #[cold]
@@ -267,7 +1105,7 @@ fn unlikely(b: bool) -> bool {
     b
 }
 
-impl Read for MmapSliceReader {
+impl Read for SliceReader {
     #[inline]
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
         if unlikely(buf.is_empty() || self.pos >= self.len) {
@@ -276,19 +1114,42 @@ impl Read for MmapSliceReader {
 
         let remaining = self.len - self.pos;
         let to_read = remaining.min(buf.len());
-        let start = self.offset + self.pos;
+        let start = self.offset.saturating_add(self.pos);
+
+        let src = (*self.source)
+            .as_ref()
+            .get(start..start.saturating_add(to_read))
+            .ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "entry is out of bounds of the archive",
+                )
+            })?;
+
+        buf[..to_read].copy_from_slice(src);
+        self.pos += to_read;
 
-        let src = &self.source[..];
+        Ok(to_read)
+    }
+}
 
-        // SAFETY: We have ensured that the ranges are valid and non-overlapping,
-        // and `buf` is not part of the mmap
-        unsafe {
-            core::ptr::copy_nonoverlapping(src.as_ptr().add(start), buf.as_mut_ptr(), to_read);
-        }
+impl Seek for SliceReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(offset) => offset,
+            SeekFrom::Current(offset) => seek_offset(self.pos as u64, offset)?,
+            SeekFrom::End(offset) => seek_offset(self.len as u64, offset)?,
+        };
 
-        self.pos += to_read;
+        if unlikely(new_pos > self.len as u64) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "seek out of bounds",
+            ));
+        }
 
-        Ok(to_read)
+        self.pos = new_pos as usize;
+        Ok(new_pos)
     }
 }
 
@@ -298,21 +1159,9 @@ impl AsyncRead for HpakEntryReader {
         _cx: &mut Context<'_>,
         buf: &mut [u8],
     ) -> Poll<std::io::Result<usize>> {
-        match &mut self.state {
-            ReaderState::Uncompressed(cursor) => match cursor.read(buf) {
-                Ok(n) => Poll::Ready(Ok(n)),
-                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => Poll::Pending,
-                Err(e) => Poll::Ready(Err(e)),
-            },
-            ReaderState::Compressed { cursor, decoder } => match decoder.read(buf) {
-                Ok(0) => Poll::Ready(Ok(0)),
-                Ok(n) => {
-                    *cursor += n as u64;
-                    Poll::Ready(Ok(n))
-                }
-                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => Poll::Pending,
-                Err(e) => Poll::Ready(Err(e)),
-            },
+        match self.verified_read(buf) {
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => Poll::Pending,
+            result => Poll::Ready(result),
         }
     }
 }
@@ -323,60 +1172,32 @@ impl AsyncSeek for HpakEntryReader {
         _cx: &mut Context<'_>,
         pos: SeekFrom,
     ) -> Poll<futures_io::Result<u64>> {
-        let offset = match pos {
-            SeekFrom::Current(offset) => {
-                if unlikely(offset < 0) {
-                    return Poll::Ready(Err(std::io::Error::new(
-                        std::io::ErrorKind::Unsupported,
-                        "backward seeking is not supported",
-                    )));
-                }
-                offset as u64
-            }
-            _ => {
-                return Poll::Ready(Err(std::io::Error::new(
-                    std::io::ErrorKind::Unsupported,
-                    "only forward seeking is supported",
-                )));
-            }
-        };
-
-        match &mut self.state {
-            ReaderState::Uncompressed(cursor) => match cursor.seek_forward(offset) {
-                Ok(new_pos) => Poll::Ready(Ok(new_pos)),
-                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => Poll::Pending,
-                Err(e) => Poll::Ready(Err(e)),
-            },
-            ReaderState::Compressed { cursor, decoder } => {
-                let mut offset = offset;
-                let mut read_buffer = vec![0u8; 4096.min(offset as usize)];
-
-                while offset > 0 {
-                    match decoder.read(&mut read_buffer[..offset.min(4096) as usize]) {
-                        Ok(0) => break,
-                        Ok(n) => {
-                            *cursor += n as u64;
-                            offset -= n as u64;
-                        }
-                        Err(e) => return Poll::Ready(Err(e)),
-                    }
-                }
-
-                Poll::Ready(Ok(*cursor))
-            }
+        match self.verified_seek(pos) {
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => Poll::Pending,
+            result => Poll::Ready(result),
         }
     }
 }
 
 impl Reader for HpakEntryReader {
+    fn read_to_end<'a>(
+        &'a mut self,
+        buf: &'a mut Vec<u8>,
+    ) -> StackFuture<'a, std::io::Result<usize>, STACK_FUTURE_SIZE> {
+        // the stored size is only a hint, a failed reservation lets `buf` grow as read
+        let _ = buf.try_reserve(self.state.remaining_hint());
+
+        StackFuture::from(futures_lite::AsyncReadExt::read_to_end(self, buf))
+    }
+
     fn seekable(
         &mut self,
     ) -> std::result::Result<&mut dyn SeekableReader, bevy::asset::io::ReaderNotSeekableError> {
-        Err(bevy::asset::io::ReaderNotSeekableError)
+        Ok(self)
     }
 }
 
-pub struct DirStream(Vec<PathBuf>);
+pub struct DirStream(pub(crate) Vec<PathBuf>);
 
 impl futures_lite::Stream for DirStream {
     type Item = PathBuf;
@@ -392,25 +1213,123 @@ mod tests {
     use futures::{AsyncSeekExt, executor::block_on};
     use rstest::rstest;
 
-    #[rstest]
-    #[case("test.png", CompressionMethod::None)]
-    #[case("test.png.zlib", CompressionMethod::Zlib)]
-    fn it_read_entry(#[case] name: &str, #[case] compression_method: CompressionMethod) {
-        let uncompressed =
-            std::fs::read(format!("{}/fuzz/test.png", env!("CARGO_MANIFEST_DIR"),)).unwrap();
+    #[test]
+    #[cfg(feature = "writer")]
+    fn it_read_written_archive() {
+        use crate::writer::HpakWriter;
 
-        let compressed =
-            File::open(format!("{}/fuzz/{}", env!("CARGO_MANIFEST_DIR"), name)).unwrap();
+        let dir = temp_dir("read-written-archive");
+        let assets = dir.join("assets");
+        std::fs::create_dir_all(assets.join("sub")).unwrap();
 
-        let mmap = unsafe { Mmap::map(&compressed).unwrap() };
+        let files = [
+            ("a.txt", CompressionMethod::None),
+            ("sub/b.txt", CompressionMethod::Zstd(3)),
+            ("sub/c.txt", CompressionMethod::Lz4),
+        ];
 
-        let mut reader = HpakEntryReader::new(
-            Arc::new(mmap),
-            0,
-            compressed.metadata().unwrap().len() as u64,
-            compression_method,
+        for (name, _) in files {
+            std::fs::write(assets.join(name), name.repeat(4096)).unwrap();
+            std::fs::write(assets.join(format!("{name}.meta")), format!("({name:?})")).unwrap();
+        }
+
+        let archive = dir.join("assets.hpak");
+        let mut writer = HpakWriter::new(&archive).unwrap();
+        writer.with_block_size(1024);
+
+        for (name, method) in files {
+            writer.add_path_with(assets.join(name), name, method);
+        }
+
+        writer.build().unwrap();
+
+        let reader = HpakReader::new(&archive)
+            .unwrap()
+            .with_checksum_verification(true);
+
+        for (name, _) in files {
+            let path = Path::new(name);
+            let mut data = Vec::new();
+            let mut meta = Vec::new();
+
+            block_on(async {
+                reader
+                    .read_data(path)
+                    .unwrap()
+                    .read_to_end(&mut data)
+                    .await
+                    .unwrap();
+                reader
+                    .read_meta(path)
+                    .unwrap()
+                    .read_to_end(&mut meta)
+                    .await
+                    .unwrap();
+            });
+
+            assert_eq!(name.repeat(4096).into_bytes(), data);
+            assert_eq!(format!("({name:?})").into_bytes(), meta);
+
+            let size = data.len() as u64;
+            assert_eq!(size, reader.uncompressed_size(path).unwrap());
+            assert_eq!(
+                Some(size),
+                reader.read_data(path).unwrap().uncompressed_size()
+            );
+        }
+
+        assert_eq!(
+            files
+                .iter()
+                .map(|(name, _)| name.len() as u64 * 4096)
+                .sum::<u64>(),
+            reader.total_uncompressed_size().unwrap()
         );
 
+        let mut paths = reader
+            .iter_files()
+            .map(|entry| entry.path().to_path_buf())
+            .collect::<Vec<_>>();
+        paths.sort();
+
+        assert_eq!(files.map(|(name, _)| PathBuf::from(name)).to_vec(), paths);
+    }
+
+    /// Returns `fuzz/test.png` and a memory map of it encoded with `compression_method`.
+    ///
+    /// Only the zlib encoded file is checked in, zstd and lz4 are encoded on the fly.
+    fn test_png(compression_method: CompressionMethod) -> (Vec<u8>, Arc<Mmap>) {
+        let fuzz = Path::new(env!("CARGO_MANIFEST_DIR")).join("fuzz");
+        let base = std::fs::read(fuzz.join("test.png")).unwrap();
+
+        let encoded = match compression_method {
+            CompressionMethod::None => base.clone(),
+            CompressionMethod::Zlib => std::fs::read(fuzz.join("test.png.zlib")).unwrap(),
+            CompressionMethod::Zstd(level) => zstd::encode_all(base.as_slice(), level).unwrap(),
+            CompressionMethod::Lz4 => {
+                let mut encoder = lz4_flex::frame::FrameEncoder::new(Vec::new());
+                encoder.write_all(&base).unwrap();
+                encoder.finish().unwrap()
+            }
+        };
+
+        let mut mmap = memmap2::MmapMut::map_anon(encoded.len()).unwrap();
+        mmap.copy_from_slice(&encoded);
+
+        (base, Arc::new(mmap.make_read_only().unwrap()))
+    }
+
+    #[rstest]
+    #[case(CompressionMethod::None)]
+    #[case(CompressionMethod::Zlib)]
+    #[case(CompressionMethod::Zstd(0))]
+    #[case(CompressionMethod::Lz4)]
+    fn it_read_entry(#[case] compression_method: CompressionMethod) {
+        let (uncompressed, mmap) = test_png(compression_method);
+
+        let size = mmap.len() as u64;
+        let mut reader = HpakEntryReader::new(mmap, 0, size, compression_method);
+
         let mut buffer = Vec::new();
 
         block_on(async { reader.read_to_end(&mut buffer).await.unwrap() });
@@ -419,21 +1338,15 @@ mod tests {
     }
 
     #[rstest]
-    #[case("test.png", CompressionMethod::None)]
-    #[case("test.png.zlib", CompressionMethod::Zlib)]
-    fn it_seek_entry(#[case] name: &str, #[case] compression_method: CompressionMethod) {
-        let base = std::fs::read(format!("{}/fuzz/test.png", env!("CARGO_MANIFEST_DIR"),)).unwrap();
+    #[case(CompressionMethod::None)]
+    #[case(CompressionMethod::Zlib)]
+    #[case(CompressionMethod::Zstd(0))]
+    #[case(CompressionMethod::Lz4)]
+    fn it_seek_entry(#[case] compression_method: CompressionMethod) {
+        let (base, mmap) = test_png(compression_method);
 
-        let encoded = File::open(format!("{}/fuzz/{}", env!("CARGO_MANIFEST_DIR"), name)).unwrap();
-
-        let mmap = unsafe { Mmap::map(&encoded).unwrap() };
-
-        let mut reader = HpakEntryReader::new(
-            Arc::new(mmap),
-            0,
-            encoded.metadata().unwrap().len() as u64,
-            compression_method,
-        );
+        let size = mmap.len() as u64;
+        let mut reader = HpakEntryReader::new(mmap, 0, size, compression_method);
 
         let mut buffer = Vec::new();
 
@@ -448,4 +1361,210 @@ mod tests {
 
         assert_eq!(base[(1024 + 8192)..], buffer);
     }
+
+    #[rstest]
+    #[case(CompressionMethod::None)]
+    #[case(CompressionMethod::Zlib)]
+    #[case(CompressionMethod::Zstd(0))]
+    #[case(CompressionMethod::Lz4)]
+    fn it_seek_entry_random_access(#[case] compression_method: CompressionMethod) {
+        use futures::AsyncReadExt;
+
+        let (base, mmap) = test_png(compression_method);
+
+        let size = mmap.len() as u64;
+        let mut reader = HpakEntryReader::new(mmap, 0, size, compression_method);
+
+        let len = base.len() as u64;
+        let mut buffer = [0u8; 256];
+
+        block_on(async {
+            let reader = reader.seekable().unwrap();
+
+            assert_eq!(len - 256, reader.seek(SeekFrom::End(-256)).await.unwrap());
+            reader.read_exact(&mut buffer).await.unwrap();
+            assert_eq!(base[(len as usize - 256)..], buffer);
+
+            assert_eq!(4096, reader.seek(SeekFrom::Start(4096)).await.unwrap());
+            reader.read_exact(&mut buffer).await.unwrap();
+            assert_eq!(base[4096..(4096 + 256)], buffer);
+
+            assert_eq!(2048, reader.seek(SeekFrom::Current(-2304)).await.unwrap());
+            reader.read_exact(&mut buffer).await.unwrap();
+            assert_eq!(base[2048..(2048 + 256)], buffer);
+
+            assert!(reader.seek(SeekFrom::Current(-4096)).await.is_err());
+        });
+    }
+
+    #[rstest]
+    #[case(CompressionMethod::None)]
+    #[case(CompressionMethod::Zlib)]
+    #[case(CompressionMethod::Zstd(0))]
+    #[case(CompressionMethod::Lz4)]
+    fn it_verify_entry_as_read(#[case] compression_method: CompressionMethod) {
+        let (base, mmap) = test_png(compression_method);
+
+        let size = mmap.len() as u64;
+        let verified = |checksum| {
+            let mut reader = HpakEntryReader::new(mmap.clone(), 0, size, compression_method);
+            reader.verifier = Some(Verifier {
+                hasher: Xxh3::new(),
+                checksum,
+                path: PathBuf::from("test.png"),
+            });
+            reader
+        };
+        let is_mismatch = |err: std::io::Error| {
+            matches!(
+                err.get_ref().and_then(|err| err.downcast_ref::<Error>()),
+                Some(Error::ChecksumMismatch(path)) if path == Path::new("test.png")
+            )
+        };
+
+        let checksum = xxh3_64(&base);
+        let mut buffer = Vec::new();
+
+        block_on(async {
+            let mut reader = verified(checksum);
+            reader.read_to_end(&mut buffer).await.unwrap();
+            assert_eq!(base, buffer);
+
+            let mut reader = verified(checksum);
+            reader.seek(SeekFrom::Start(4096)).await.unwrap();
+            reader.seek(SeekFrom::Start(0)).await.unwrap();
+            buffer.clear();
+            reader.read_to_end(&mut buffer).await.unwrap();
+            assert_eq!(base, buffer);
+
+            let mut reader = verified(!checksum);
+            buffer.clear();
+            assert!(is_mismatch(
+                reader.read_to_end(&mut buffer).await.unwrap_err()
+            ));
+
+            let mut reader = verified(!checksum);
+            let mut prefix = [0u8; 256];
+            futures::AsyncReadExt::read_exact(&mut reader, &mut prefix)
+                .await
+                .unwrap();
+            assert_eq!(base[..256], prefix);
+            assert!(is_mismatch(
+                reader.seek(SeekFrom::End(-256)).await.unwrap_err()
+            ));
+        });
+    }
+
+    #[test]
+    fn it_reject_out_of_bounds_entry() {
+        let mut mmap = memmap2::MmapMut::map_anon(64).unwrap();
+        mmap.fill(1);
+        let mmap = Arc::new(mmap.make_read_only().unwrap());
+
+        let mut buffer = Vec::new();
+
+        for (offset, size) in [(32, 64), (128, 16), (u64::MAX - 8, 16)] {
+            let mut reader =
+                HpakEntryReader::new(mmap.clone(), offset, size, CompressionMethod::None);
+            let err = block_on(async { reader.read_to_end(&mut buffer).await.unwrap_err() });
+
+            assert_eq!(std::io::ErrorKind::UnexpectedEof, err.kind());
+        }
+    }
+
+    #[test]
+    fn it_ignore_corrupted_uncompressed_size() {
+        let (base, mmap) = test_png(CompressionMethod::Zstd(0));
+
+        let size = mmap.len() as u64;
+        let mut reader = HpakEntryReader::new(mmap, 0, size, CompressionMethod::Zstd(0));
+        match &mut reader.state {
+            ReaderState::Compressed(compressed) => compressed.len = Some(u64::MAX),
+            _ => unreachable!(),
+        }
+
+        let mut buffer = Vec::new();
+        block_on(async { reader.read_to_end(&mut buffer).await.unwrap() });
+
+        assert_eq!(base, buffer);
+    }
+
+    #[rstest]
+    #[case(16, &[32, 32])]
+    #[case(16, &[32, u64::MAX])]
+    #[case(0, &[32])]
+    fn it_reject_invalid_block_table(#[case] block_size: u64, #[case] blocks: &[u64]) {
+        let mmap = memmap2::MmapMut::map_anon(48).unwrap();
+        let mmap = Arc::new(mmap.make_read_only().unwrap());
+
+        assert!(matches!(
+            HpakEntryReader::new_chunked(mmap, 0, CompressionMethod::Lz4, block_size, blocks),
+            Err(Error::InvalidFileFormat)
+        ));
+    }
+
+    #[rstest]
+    #[case(CompressionMethod::Zlib, 32 * 1024)]
+    #[case(CompressionMethod::Zstd(3), 48_000)]
+    #[case(CompressionMethod::Lz4, 4096)]
+    fn it_read_and_seek_chunked_entry(
+        #[case] compression_method: CompressionMethod,
+        #[case] block_size: u64,
+    ) {
+        use futures::AsyncReadExt;
+
+        let mut base =
+            std::fs::read(format!("{}/fuzz/test.png", env!("CARGO_MANIFEST_DIR"),)).unwrap();
+        base.truncate(128 * 1024);
+
+        let mut encoded = Vec::new();
+        let blocks = base
+            .chunks(block_size as usize)
+            .map(|block| {
+                compression_method
+                    .compress(ZlibEncoder::default(), block, &mut encoded)
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        let mut mmap = memmap2::MmapMut::map_anon(encoded.len()).unwrap();
+        mmap.copy_from_slice(&encoded);
+        let mmap = Arc::new(mmap.make_read_only().unwrap());
+
+        let new_reader = || {
+            HpakEntryReader::new_chunked(mmap.clone(), 0, compression_method, block_size, &blocks)
+                .unwrap()
+        };
+
+        let mut buffer = Vec::new();
+        block_on(async {
+            Reader::read_to_end(&mut new_reader(), &mut buffer)
+                .await
+                .unwrap()
+        });
+        assert_eq!(base, buffer);
+
+        let len = base.len() as u64;
+        let mut reader = new_reader();
+        let mut buffer = [0u8; 256];
+
+        block_on(async {
+            assert_eq!(len - 256, reader.seek(SeekFrom::End(-256)).await.unwrap());
+            reader.read_exact(&mut buffer).await.unwrap();
+            assert_eq!(base[(len as usize - 256)..], buffer);
+
+            // read across a block boundary
+            let boundary = block_size * 2 - 128;
+            assert_eq!(
+                boundary,
+                reader.seek(SeekFrom::Start(boundary)).await.unwrap()
+            );
+            reader.read_exact(&mut buffer).await.unwrap();
+            assert_eq!(base[boundary as usize..(boundary as usize + 256)], buffer);
+
+            assert_eq!(128, reader.seek(SeekFrom::Start(128)).await.unwrap());
+            reader.read_exact(&mut buffer).await.unwrap();
+            assert_eq!(base[128..(128 + 256)], buffer);
+        });
+    }
 }
@@ -0,0 +1,207 @@
+/// Size of the Ed25519 signature of signed archives.
+pub(crate) const SIGNATURE_SIZE: usize = 64;
+
+#[cfg(feature = "signing")]
+mod signer {
+    use super::*;
+    use ed25519_dalek::{Signature, VerifyingKey};
+
+    /// Signer of archives with an Ed25519 secret key.
+    #[cfg(feature = "writer")]
+    pub(crate) struct ArchiveSigner(ed25519_dalek::SigningKey);
+
+    #[cfg(feature = "writer")]
+    impl ArchiveSigner {
+        pub(crate) fn new(key: &[u8; 32]) -> Self {
+            Self(ed25519_dalek::SigningKey::from_bytes(key))
+        }
+
+        /// Sign the encoded `header`, ending with a placeholder signature, and the
+        /// entry `table` of an archive.
+        pub(crate) fn sign(&self, header: &[u8], table: &[u8]) -> [u8; SIGNATURE_SIZE] {
+            use ed25519_dalek::Signer;
+
+            self.0.sign(&signed_bytes(header, table)).to_bytes()
+        }
+    }
+
+    #[cfg(all(feature = "writer", feature = "debug-impls"))]
+    impl std::fmt::Debug for ArchiveSigner {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.write_str("ArchiveSigner")
+        }
+    }
+
+    /// Returns `true` if `signature` is a valid signature of the encoded `header`,
+    /// ending with the signature, and the entry `table` of an archive for `public_key`.
+    pub(crate) fn check_signature(
+        public_key: &[u8; 32],
+        header: &[u8],
+        table: &[u8],
+        signature: &[u8; SIGNATURE_SIZE],
+    ) -> bool {
+        VerifyingKey::from_bytes(public_key)
+            .and_then(|key| {
+                key.verify_strict(
+                    &signed_bytes(header, table),
+                    &Signature::from_bytes(signature),
+                )
+            })
+            .is_ok()
+    }
+
+    /// Returns the bytes covered by the signature of an archive: its header up to
+    /// the signature itself, followed by its entry table holding the checksums of
+    /// every entry.
+    fn signed_bytes(header: &[u8], table: &[u8]) -> Vec<u8> {
+        [&header[..header.len() - SIGNATURE_SIZE], table].concat()
+    }
+}
+
+/// Stand-in for the signer when the `signing` feature is disabled, it cannot be
+/// constructed so archives are never signed.
+#[cfg(all(not(feature = "signing"), feature = "writer"))]
+mod signer {
+    use super::*;
+
+    #[cfg_attr(feature = "debug-impls", derive(Debug))]
+    pub(crate) enum ArchiveSigner {}
+
+    impl ArchiveSigner {
+        pub(crate) fn sign(&self, _header: &[u8], _table: &[u8]) -> [u8; SIGNATURE_SIZE] {
+            match *self {}
+        }
+    }
+}
+
+#[cfg(any(feature = "signing", feature = "writer"))]
+pub(crate) use signer::*;
+
+#[cfg(all(test, feature = "signing", feature = "writer"))]
+mod tests {
+    use crate::{Error, HpakReader, encoding::*, format::*};
+    use bevy::asset::io::AssetReader;
+    use futures_lite::{AsyncReadExt, AsyncSeekExt, future::block_on};
+    use xxhash_rust::xxh3::xxh3_64;
+
+    const KEY: [u8; 32] = [3; 32];
+
+    fn public_key(key: &[u8; 32]) -> [u8; 32] {
+        ed25519_dalek::SigningKey::from_bytes(key)
+            .verifying_key()
+            .to_bytes()
+    }
+
+    #[test]
+    fn it_verify_signed_archive() {
+        let dir = temp_dir("signed");
+        let signed = write_archive(&dir, "signed.hpak", |writer| {
+            writer.with_signing_key(&KEY).add_bytes("a.txt", "()", "a");
+        });
+        let unsigned = write_archive(&dir, "unsigned.hpak", |writer| {
+            writer.add_bytes("a.txt", "()", "a");
+        });
+
+        let reader = HpakReader::new(&signed).unwrap();
+        assert!(reader.is_signed());
+        assert!(reader.verify_signature(&public_key(&KEY)).is_ok());
+        assert!(matches!(
+            reader.verify_signature(&public_key(&[4; 32])),
+            Err(Error::InvalidSignature)
+        ));
+
+        let reader = HpakReader::new(&unsigned).unwrap();
+        assert!(!reader.is_signed());
+        assert!(matches!(
+            reader.verify_signature(&public_key(&KEY)),
+            Err(Error::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn it_reject_tampered_entries_table() {
+        let dir = temp_dir("signed-tampered");
+        let archive = write_archive(&dir, "assets.hpak", |writer| {
+            writer.with_signing_key(&KEY).add_bytes("a.txt", "()", "a");
+        });
+
+        // rename the entry and fix the checksum of the entry table
+        let mut bytes = std::fs::read(&archive).unwrap();
+        let mut header = HpakHeader::decode(&bytes[..]).unwrap();
+        let start = header.entries_offset as usize;
+        let position = bytes[start..]
+            .windows(5)
+            .position(|w| w == b"a.txt")
+            .unwrap();
+        bytes[start + position] = b'b';
+
+        header.entries_checksum = xxh3_64(&bytes[start..]);
+        header.encode(&mut bytes[..]).unwrap();
+        std::fs::write(&archive, bytes).unwrap();
+
+        let reader = HpakReader::new(&archive).unwrap();
+        assert!(matches!(
+            reader.verify_signature(&public_key(&KEY)),
+            Err(Error::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn it_reject_tampered_entries_of_verified_archive() {
+        let dir = temp_dir("signed-tampered-entry");
+        let archive = write_archive(&dir, "assets.hpak", |writer| {
+            writer
+                .with_signing_key(&KEY)
+                .with_block_size(1024)
+                .add_bytes_with("a.txt", "()", "a".repeat(4096), CompressionMethod::None)
+                .add_bytes_with("b.txt", "()", "b".repeat(4096), CompressionMethod::Lz4);
+        });
+
+        // tamper the last byte of `a.txt`, which is only verified by reading to the end or seeking
+        let reader = HpakReader::new(&archive).unwrap();
+        let entry = reader.get_entry(Path::new("a.txt")).unwrap();
+        let last = (entry.meta_offset + entry.meta_size + entry.data_size - 1) as usize;
+        drop(reader);
+
+        let mut bytes = std::fs::read(&archive).unwrap();
+        bytes[last] = b'x';
+        std::fs::write(&archive, bytes).unwrap();
+
+        let reader = HpakReader::new(&archive)
+            .unwrap()
+            .with_checksum_verification(true);
+        assert!(reader.verify_signature(&public_key(&KEY)).is_ok());
+
+        let is_mismatch = |err: std::io::Error| {
+            matches!(
+                err.get_ref().and_then(|err| err.downcast_ref::<Error>()),
+                Some(Error::ChecksumMismatch(path)) if path == Path::new("a.txt")
+            )
+        };
+
+        let mut data = Vec::new();
+        let mut asset = block_on(AssetReader::read(&reader, Path::new("a.txt"))).unwrap();
+        assert!(is_mismatch(
+            block_on(asset.read_to_end(&mut data)).unwrap_err()
+        ));
+
+        let mut entry = reader.read_data(Path::new("a.txt")).unwrap();
+        let mut buffer = [0u8; 16];
+        block_on(async {
+            entry.read_exact(&mut buffer).await.unwrap();
+            assert!(is_mismatch(
+                entry.seek(std::io::SeekFrom::Start(0)).await.unwrap_err()
+            ));
+        });
+        assert_eq!([b'a'; 16], buffer);
+
+        // untampered entries are still read and seeked freely
+        let mut reader = reader.read_data(Path::new("b.txt")).unwrap();
+        let mut buffer = [0u8; 16];
+        block_on(async {
+            reader.seek(std::io::SeekFrom::Start(2048)).await.unwrap();
+            reader.read_exact(&mut buffer).await.unwrap();
+        });
+        assert_eq!([b'b'; 16], buffer);
+    }
+}
@@ -0,0 +1,424 @@
+use super::*;
+use crate::Result;
+use memmap2::Mmap;
+use std::sync::Arc;
+use xxhash_rust::xxh3::xxh3_64;
+
+/// Report produced by [`verify_archive`] and [`HpakReader::verify`].
+#[cfg_attr(any(test, feature = "debug-impls"), derive(Debug))]
+#[derive(Clone, Default)]
+pub struct VerifyReport {
+    /// Number of file entries checked.
+    pub entries: usize,
+    /// Issues found in the archive, empty if the archive is valid.
+    pub issues: Vec<VerifyIssue>,
+}
+
+impl VerifyReport {
+    /// Returns `true` if no issue was found in the archive.
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+/// An issue found while verifying an HPAK archive.
+///
+/// File entries are identified by their path in the archive and the hash of
+/// their path.
+#[cfg_attr(any(test, feature = "debug-impls"), derive(Debug))]
+#[derive(Clone, PartialEq)]
+pub enum VerifyIssue {
+    /// The entry table does not match the checksum stored in the header.
+    EntriesChecksumMismatch,
+    /// The entry table cannot be decoded, no entry could be checked.
+    InvalidEntries(String),
+    /// The metadata or data of an entry lies outside of the archive's content.
+    OutOfBounds { path: PathBuf, hash: u64 },
+    /// The metadata or data of two entries overlap.
+    Overlap { path: PathBuf, other: PathBuf },
+    /// The block table of an entry does not add up to its data size.
+    InvalidBlocks { path: PathBuf, hash: u64 },
+    /// The metadata or data of an entry cannot be decompressed.
+    DecompressionFailed {
+        path: PathBuf,
+        hash: u64,
+        error: String,
+    },
+    /// The decompressed metadata of an entry does not match its checksum.
+    MetaChecksumMismatch { path: PathBuf, hash: u64 },
+    /// The decompressed data of an entry does not match its checksum.
+    DataChecksumMismatch { path: PathBuf, hash: u64 },
+}
+
+/// Verify the integrity of the HPAK archive at `path`.
+///
+/// This checks that:
+/// - the entry table matches the checksum stored in the header,
+/// - every entry's metadata and data lie between the header and the entry table,
+/// - no two entries overlap,
+/// - every entry decompresses successfully and matches its checksums.
+///
+/// Unlike [`HpakReader::new`], a corrupted entry table is reported as a
+/// [`VerifyIssue`] instead of an error.
+///
+/// The content of encrypted entries cannot be checked without the key, nor any
+/// entry if the entry table is encrypted. Use [`HpakReader::verify`] on a reader
+/// opened with the key instead.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be opened or its header is invalid.
+pub fn verify_archive(path: impl AsRef<Path>) -> Result<VerifyReport> {
+    let file = open_archive(path)?;
+    let mmap = unsafe { Mmap::map(&file)? };
+
+    verify_mmap(&Arc::new(mmap), None)
+}
+
+pub(crate) fn verify_mmap(
+    mmap: &Arc<Mmap>,
+    cipher: Option<&ArchiveCipher>,
+) -> Result<VerifyReport> {
+    let (header, header_size) = HpakHeader::from_archive(mmap)?;
+    let header_size = header_size as u64;
+    let table = header.entries_table(mmap)?;
+
+    let mut report = VerifyReport::default();
+
+    // a corrupted entry table can't be trusted to locate the entries
+    if xxh3_64(table) != header.entries_checksum {
+        report.issues.push(VerifyIssue::EntriesChecksumMismatch);
+        return Ok(report);
+    }
+
+    // the entry table can't be listed without the key if encrypted
+    let decrypted;
+    let table = match (&header.entries_nonce, cipher) {
+        (None, _) => table,
+        (Some(nonce), Some(cipher)) => match cipher.decrypt_entries(nonce, table) {
+            Ok(table) => {
+                decrypted = table;
+                &decrypted[..]
+            }
+            Err(err) => {
+                report
+                    .issues
+                    .push(VerifyIssue::InvalidEntries(err.to_string()));
+                return Ok(report);
+            }
+        },
+        (Some(_), None) => return Ok(report),
+    };
+
+    let entries = match HpakEntries::decode_version(table, header.version) {
+        Ok(entries) => entries,
+        Err(err) => {
+            report
+                .issues
+                .push(VerifyIssue::InvalidEntries(err.to_string()));
+            return Ok(report);
+        }
+    };
+
+    let mut ranges = Vec::with_capacity(entries.files.len());
+
+    for entry in entries.files.iter() {
+        report.entries += 1;
+
+        let hash = entry.hash;
+        let path = || entry.path.clone();
+        let end = entry
+            .meta_offset
+            .checked_add(entry.meta_size)
+            .and_then(|end| end.checked_add(entry.data_size));
+
+        match end {
+            Some(end) if entry.meta_offset >= header_size && end <= header.entries_offset => {
+                ranges.push((entry.meta_offset, end, &entry.path));
+            }
+            _ => {
+                report
+                    .issues
+                    .push(VerifyIssue::OutOfBounds { path: path(), hash });
+                continue;
+            }
+        }
+
+        let blocks_size = entry.blocks.iter().copied().try_fold(0, u64::checked_add);
+
+        if entry.block_size > 0 && blocks_size != Some(entry.data_size) {
+            report
+                .issues
+                .push(VerifyIssue::InvalidBlocks { path: path(), hash });
+            continue;
+        }
+
+        let entry_cipher = || cipher.and_then(|cipher| cipher.entry(entry));
+
+        // the content of encrypted entries can't be checked without the key
+        if entry.is_encrypted() && cipher.is_none() {
+            continue;
+        }
+
+        // entries without metadata store an empty, uncompressed metadata block
+        let meta = match entry.meta_size {
+            0 => Ok(xxh3_64(&[])),
+            _ => HpakEntryReader::for_meta(
+                mmap.clone(),
+                entry,
+                header.meta_compression_method,
+                entry_cipher(),
+            )
+            .map_err(std::io::Error::other)
+            .and_then(digest),
+        };
+
+        match meta {
+            Ok(checksum) if entry.legacy || checksum == entry.meta_checksum => {}
+            Ok(_) => report
+                .issues
+                .push(VerifyIssue::MetaChecksumMismatch { path: path(), hash }),
+            Err(err) => report.issues.push(VerifyIssue::DecompressionFailed {
+                path: path(),
+                hash,
+                error: err.to_string(),
+            }),
+        }
+
+        let data = HpakEntryReader::for_data(mmap.clone(), entry, entry_cipher())
+            .map_err(std::io::Error::other)
+            .and_then(digest);
+
+        match data {
+            Ok(checksum) if entry.legacy || checksum == entry.data_checksum => {}
+            Ok(_) => report
+                .issues
+                .push(VerifyIssue::DataChecksumMismatch { path: path(), hash }),
+            Err(err) => report.issues.push(VerifyIssue::DecompressionFailed {
+                path: path(),
+                hash,
+                error: err.to_string(),
+            }),
+        }
+    }
+
+    report.issues.extend(overlaps(ranges));
+
+    Ok(report)
+}
+
+/// Returns a [`VerifyIssue::Overlap`] for each of the `(start, end, path)` ranges
+/// overlapping a range starting before it.
+fn overlaps(mut ranges: Vec<(u64, u64, &PathBuf)>) -> Vec<VerifyIssue> {
+    ranges.sort_unstable();
+
+    let mut issues = Vec::new();
+    // the range reaching the furthest among the ranges seen so far
+    let mut furthest: Option<(u64, &PathBuf)> = None;
+
+    for (start, end, path) in ranges {
+        match furthest {
+            Some((furthest_end, other)) if furthest_end > start => {
+                issues.push(VerifyIssue::Overlap {
+                    path: other.clone(),
+                    other: path.clone(),
+                });
+
+                if end > furthest_end {
+                    furthest = Some((end, path));
+                }
+            }
+            _ => furthest = Some((end, path)),
+        }
+    }
+
+    issues
+}
+
+/// Read `reader` to the end, returning the XXH3 digest of its content.
+fn digest(mut reader: HpakEntryReader) -> std::io::Result<u64> {
+    use futures_lite::AsyncReadExt;
+
+    let mut hasher = Xxh3::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+
+    futures_lite::future::block_on(async {
+        loop {
+            match reader.read(&mut buffer).await? {
+                0 => return Ok(hasher.digest()),
+                n => hasher.update(&buffer[..n]),
+            }
+        }
+    })
+}
+
+#[cfg(all(test, feature = "writer"))]
+mod tests {
+    use super::*;
+
+    fn write_verified_archive(dir: &Path) -> PathBuf {
+        write_archive(dir, "assets.hpak", |writer| {
+            writer
+                .with_alignment(0)
+                .add_bytes_with(
+                    "a.txt",
+                    "()",
+                    "a.txt".repeat(1024),
+                    CompressionMethod::Zstd(0),
+                )
+                .add_bytes_with("b.txt", "()", "b.txt".repeat(1024), CompressionMethod::None);
+        })
+    }
+
+    #[test]
+    fn it_verify_valid_archive() {
+        let dir = temp_dir("verify-valid");
+        let archive = write_verified_archive(&dir);
+
+        let report = verify_archive(&archive).unwrap();
+        assert!(report.is_ok(), "{:?}", report.issues);
+        assert_eq!(2, report.entries);
+
+        let report = HpakReader::new(&archive).unwrap().verify().unwrap();
+        assert!(report.is_ok(), "{:?}", report.issues);
+    }
+
+    #[test]
+    fn it_report_corrupted_data() {
+        let dir = temp_dir("verify-corrupted-data");
+        let archive = write_verified_archive(&dir);
+
+        let mut bytes = std::fs::read(&archive).unwrap();
+        let position = bytes.windows(5).position(|w| w == b"b.txt").unwrap();
+        bytes[position] = b'c';
+        std::fs::write(&archive, bytes).unwrap();
+
+        let report = verify_archive(&archive).unwrap();
+        assert_eq!(
+            vec![VerifyIssue::DataChecksumMismatch {
+                path: PathBuf::from("b.txt"),
+                hash: hash_path("b.txt")
+            }],
+            report.issues
+        );
+    }
+
+    #[test]
+    fn it_report_corrupted_entries() {
+        let dir = temp_dir("verify-corrupted-entries");
+        let archive = write_verified_archive(&dir);
+
+        let mut bytes = std::fs::read(&archive).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xFF;
+        std::fs::write(&archive, bytes).unwrap();
+
+        let report = verify_archive(&archive).unwrap();
+        assert!(
+            report
+                .issues
+                .contains(&VerifyIssue::EntriesChecksumMismatch)
+        );
+        assert!(matches!(
+            HpakReader::new(&archive),
+            Err(crate::Error::EntriesChecksumMismatch)
+        ));
+    }
+
+    #[test]
+    fn it_report_overlapping_entries() {
+        let [a, b, c, d] = ["a", "b", "c", "d"].map(PathBuf::from);
+        let overlap = |path: &PathBuf, other: &PathBuf| VerifyIssue::Overlap {
+            path: path.clone(),
+            other: other.clone(),
+        };
+
+        assert_eq!(
+            vec![overlap(&a, &b), overlap(&a, &c)],
+            overlaps(vec![
+                (30, 40, &c),
+                (0, 100, &a),
+                (100, 110, &d),
+                (10, 20, &b)
+            ])
+        );
+        assert!(overlaps(vec![(0, 10, &a), (10, 10, &b), (10, 20, &c)]).is_empty());
+    }
+
+    #[test]
+    fn it_report_overflowing_blocks() {
+        let dir = temp_dir("verify-overflowing-blocks");
+        let archive = write_archive(&dir, "assets.hpak", |writer| {
+            writer.with_block_size(1024).add_bytes_with(
+                "a.txt",
+                "()",
+                "a.txt".repeat(1024),
+                CompressionMethod::Lz4,
+            );
+        });
+
+        let reader = HpakReader::new(&archive).unwrap();
+        let entry = reader.get_entry(Path::new("a.txt")).unwrap().clone();
+        drop(reader);
+
+        let mut bytes = std::fs::read(&archive).unwrap();
+        let (header, _) = HpakHeader::from_archive(&bytes).unwrap();
+        let table = header.entries_offset as usize..bytes.len();
+
+        // make the sizes of the blocks overflow, with a matching table checksum
+        let blocks = entry
+            .blocks
+            .iter()
+            .flat_map(|size| size.to_le_bytes())
+            .collect::<Vec<_>>();
+        let position = table.start
+            + bytes[table.clone()]
+                .windows(blocks.len())
+                .position(|w| w == blocks)
+                .unwrap();
+        bytes[position..position + 8].fill(0xFF);
+
+        let checksum = header.entries_checksum.to_le_bytes();
+        let checksum_offset = bytes.windows(8).position(|w| w == checksum).unwrap();
+        let checksum = xxh3_64(&bytes[table]).to_le_bytes();
+        bytes[checksum_offset..checksum_offset + 8].copy_from_slice(&checksum);
+        std::fs::write(&archive, bytes).unwrap();
+
+        let report = verify_archive(&archive).unwrap();
+        assert_eq!(
+            vec![VerifyIssue::InvalidBlocks {
+                path: PathBuf::from("a.txt"),
+                hash: hash_path("a.txt")
+            }],
+            report.issues
+        );
+    }
+
+    #[test]
+    fn it_report_invalid_entries() {
+        let dir = temp_dir("verify-invalid-entries");
+        let archive = write_verified_archive(&dir);
+
+        let original = std::fs::read(&archive).unwrap();
+        let (header, _) = HpakHeader::from_archive(&original).unwrap();
+        let checksum = header.entries_checksum.to_le_bytes();
+        let checksum_offset = original.windows(8).position(|w| w == checksum).unwrap();
+        let table = header.entries_offset as usize..original.len();
+
+        // huge lengths anywhere in the table, with or without a matching checksum
+        for offset in table.start..table.end - 8 {
+            let mut bytes = original.clone();
+            bytes[offset..offset + 8].fill(0xFF);
+            std::fs::write(&archive, &bytes).unwrap();
+
+            let report = verify_archive(&archive).unwrap();
+            assert_eq!(vec![VerifyIssue::EntriesChecksumMismatch], report.issues);
+
+            let checksum = xxh3_64(&bytes[table.clone()]).to_le_bytes();
+            bytes[checksum_offset..checksum_offset + 8].copy_from_slice(&checksum);
+            std::fs::write(&archive, &bytes).unwrap();
+
+            let _ = verify_archive(&archive).unwrap();
+        }
+    }
+}
@@ -1,15 +1,24 @@
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, BTreeSet},
     fs::{self, File, OpenOptions},
-    io::{Read, Seek, SeekFrom},
+    io::{Cursor, Read, Seek, SeekFrom},
     path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
 };
 
-use bevy::platform::collections::HashMap;
+use bevy::platform::collections::{HashMap, HashSet};
+use futures_lite::io::BlockOn;
+use parking_lot::Mutex;
+use xxhash_rust::xxh3::xxh3_64;
 
 use super::*;
 use crate::{Error, Result, encoding::*};
 
+pub use super::ZlibEncoder;
+
 /// Writer for creating HPAK archives.
 ///
 /// This type implements a builder-style API for configuring how files
@@ -29,6 +38,7 @@ use crate::{Error, Result, encoding::*};
 ///     .default_data_compression(CompressionMethod::Zlib)
 ///     .minify_metadata(true)
 ///     .with_alignment(4096)
+///     .with_block_size(256 * 1024)
 ///     .add_paths_from_dir("assets")?
 ///     .build()?;
 /// # Ok(())
@@ -43,12 +53,30 @@ pub struct HpakWriter {
     default_data_compression: CompressionMethod,
     /// Per-extension default compression methods.
     default_compression_by_extension: HashMap<String, CompressionMethod>,
-    /// Paths queued to be added to the archive.
-    queued_paths: BTreeMap<PathBuf, (PathBuf, Option<CompressionMethod>)>,
+    /// Entries queued to be added to the archive.
+    queued: Vec<QueuedEntry>,
     entries: BTreeMap<PathBuf, HpakFileEntry>,
+    /// Paths of the files deleted by the archive.
+    tombstones: BTreeSet<PathBuf>,
     alignment: Option<u64>,
+    /// Uncompressed size of independently compressed data blocks, if any.
+    block_size: Option<u64>,
     /// Whether the metadata should be minified before being written.
     minify_metadata: bool,
+    /// Number of threads compressing entries, `0` to use the available parallelism.
+    threads: usize,
+    /// Encoder used for [`CompressionMethod::Zlib`].
+    zlib_encoder: ZlibEncoder,
+    /// Archive whose compressed entries are reused when unchanged.
+    previous: Option<HpakReader>,
+    /// Synthesizes the metadata of entries without any.
+    default_meta: Option<DefaultMeta>,
+    /// Cipher encrypting the entries, if any.
+    cipher: Option<ArchiveCipher>,
+    /// Whether the entry table is encrypted with `cipher`.
+    encrypt_entries: bool,
+    signer: Option<ArchiveSigner>,
+    user_data: Option<Vec<u8>>,
     finalized: bool,
 }
 
@@ -70,11 +98,21 @@ impl HpakWriter {
         Ok(Self {
             output,
             meta_compression: CompressionMethod::None,
-            default_data_compression: CompressionMethod::None,
+            default_data_compression: CompressionMethod::default(),
             default_compression_by_extension: HashMap::new(),
-            queued_paths: BTreeMap::new(),
+            queued: Vec::new(),
             entries: BTreeMap::new(),
+            tombstones: BTreeSet::new(),
             alignment: Some(4096),
+            block_size: None,
+            threads: 0,
+            zlib_encoder: ZlibEncoder::default(),
+            previous: None,
+            default_meta: None,
+            cipher: None,
+            encrypt_entries: false,
+            signer: None,
+            user_data: None,
             finalized: false,
             minify_metadata: true,
         })
@@ -88,10 +126,10 @@ impl HpakWriter {
         self
     }
 
-    /// Set the default compression method for file data when no per-file
-    /// override is provided.
+    /// Set the default compression method for file data when neither a per-file
+    /// nor a per-extension override is provided.
     ///
-    /// Defaults to [`CompressionMethod::None`].
+    /// Defaults to [`CompressionMethod::default`], i.e. [`CompressionMethod::None`].
     pub fn default_data_compression(&mut self, method: CompressionMethod) -> &mut Self {
         self.default_data_compression = method;
         self
@@ -119,6 +157,137 @@ impl HpakWriter {
         self
     }
 
+    /// Split compressed data into independently compressed blocks of `block_size`
+    /// uncompressed bytes.
+    ///
+    /// Each entry stores a table of its blocks, so seeking into compressed data only
+    /// decompresses the block containing the target position instead of everything
+    /// before it. Smaller blocks make seeking cheaper at the cost of compression ratio.
+    /// Uncompressed entries are only split if encrypted, so they are decrypted block
+    /// by block too.
+    ///
+    /// `0` (the default) disables blocks: data is compressed as a single stream.
+    pub fn with_block_size(&mut self, block_size: u64) -> &mut Self {
+        if block_size == 0 {
+            self.block_size = None;
+        } else {
+            self.block_size = Some(block_size);
+        }
+
+        self
+    }
+
+    /// Set the encoder used to compress metadata and data with [`CompressionMethod::Zlib`].
+    ///
+    /// Use [`ZlibEncoder::Flate2`] for fast iterative builds and [`ZlibEncoder::Zopfli`]
+    /// for the smallest archives. This doesn't change the archive format.
+    ///
+    /// Defaults to [`ZlibEncoder::default`].
+    pub fn zlib_encoder(&mut self, encoder: ZlibEncoder) -> &mut Self {
+        self.zlib_encoder = encoder;
+        self
+    }
+
+    /// Synthesize the metadata of entries without any.
+    ///
+    /// Entries without a `.meta` file, or queued with empty metadata, are stored
+    /// without metadata: the reader then reports their metadata as not found and
+    /// Bevy falls back to the default metadata of the asset's loader. `default_meta`
+    /// is called with the archive path of such entries and can return their
+    /// metadata instead, for example the serialized default meta of their loader.
+    /// Returning `None` keeps the entry without metadata.
+    pub fn with_default_meta(
+        &mut self,
+        default_meta: impl Fn(&Path) -> Option<Vec<u8>> + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.default_meta = Some(DefaultMeta(Box::new(default_meta)));
+        self
+    }
+
+    /// Reuse the compressed entries of a previously built archive.
+    ///
+    /// When building, an entry whose metadata and data are unchanged since the
+    /// previous archive is copied from it instead of being compressed again, as long
+    /// as its compression method, the metadata compression method and the block
+    /// size are the same. This makes repacking after a few changes much faster.
+    ///
+    /// The [`ZlibEncoder`] is not stored in archives: reused zlib entries keep the
+    /// encoder they were compressed with, so release builds should not use a previous archive.
+    /// Encrypted entries are only reused if both archives are encrypted with the same key,
+    /// which must be set before the previous archive if its entry table is encrypted.
+    ///
+    /// `path` must not be the output of this writer, which is truncated by [`HpakWriter::new`].
+    /// Copy or rename the previous archive first.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the previous archive cannot be opened or is invalid.
+    pub fn with_previous_archive(&mut self, path: impl AsRef<Path>) -> Result<&mut Self> {
+        self.previous = Some(HpakReader::open_unchecked(path, self.cipher.as_ref())?);
+        Ok(self)
+    }
+
+    /// Set the number of threads used to compress entries.
+    ///
+    /// Entries are always written in the same order regardless of the number of
+    /// threads, so the archive is identical for any value.
+    ///
+    /// `0` (the default) uses [`std::thread::available_parallelism`].
+    pub fn with_threads(&mut self, threads: usize) -> &mut Self {
+        self.threads = threads;
+        self
+    }
+
+    /// Encrypt the metadata and data of every entry with `key` using ChaCha20-Poly1305.
+    ///
+    /// Each entry is encrypted with its own random nonce, the archive can then only
+    /// be read with the same key, see `HpakReader::new_encrypted`. Paths are still
+    /// stored in clear in the entry table, unless [`HpakWriter::encrypt_entries_table`]
+    /// is enabled.
+    ///
+    /// Encrypted data stored as a single stream is decrypted entirely into memory when
+    /// it is opened for reading. Set a block size with [`HpakWriter::with_block_size`]
+    /// to split every encrypted entry, compressed or not, into blocks decrypted as they
+    /// are read.
+    #[cfg(feature = "encryption")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "encryption")))]
+    pub fn with_encryption_key(&mut self, key: &[u8; 32]) -> &mut Self {
+        self.cipher = Some(ArchiveCipher::new(key));
+        self
+    }
+
+    /// Encrypt the entry table too, so the paths of the entries can't be listed
+    /// without the key set with [`HpakWriter::with_encryption_key`].
+    ///
+    /// The archive can't be verified without the key either, see [`verify_archive`].
+    #[cfg(feature = "encryption")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "encryption")))]
+    pub fn encrypt_entries_table(&mut self, encrypt: bool) -> &mut Self {
+        self.encrypt_entries = encrypt;
+        self
+    }
+
+    /// Sign the header and entry table, which holds the checksums of every entry,
+    /// with the Ed25519 secret `key`.
+    ///
+    /// Signed archives can then be checked with [`HpakReader::verify_signature`]
+    /// and the public key of `key`.
+    #[cfg(feature = "signing")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "signing")))]
+    pub fn with_signing_key(&mut self, key: &[u8; 32]) -> &mut Self {
+        self.signer = Some(ArchiveSigner::new(key));
+        self
+    }
+
+    /// Store arbitrary bytes in the header of the archive, e.g. a build identifier
+    /// or a content version, read back with [`HpakReader::user_data`].
+    ///
+    /// They are covered by the signature of signed archives but never encrypted.
+    pub fn with_user_data(&mut self, data: impl Into<Vec<u8>>) -> &mut Self {
+        self.user_data = Some(data.into());
+        self
+    }
+
     /// Set the default compression method for a specific file extension.
     ///
     /// If the extension already has a default, it will be overwritten.
@@ -134,15 +303,18 @@ impl HpakWriter {
 
     /// Queue a path to be added to the archive using the default compression
     /// strategy.
+    ///
+    /// The metadata is read from the `.meta` file next to `disk_path`, if any.
     pub fn add_path(
         &mut self,
         disk_path: impl AsRef<Path>,
         archive_path: impl AsRef<Path>,
     ) -> &mut Self {
-        let key = disk_path.as_ref().to_path_buf();
-        self.queued_paths
-            .insert(key, (archive_path.as_ref().to_path_buf(), None));
-        self
+        self.queue(
+            archive_path,
+            EntrySource::Path(disk_path.as_ref().to_path_buf()),
+            None,
+        )
     }
 
     /// Queue a path with an explicit compression method for its data.
@@ -152,14 +324,160 @@ impl HpakWriter {
         archive_path: impl AsRef<Path>,
         compression_method: CompressionMethod,
     ) -> &mut Self {
-        let key = disk_path.as_ref().to_path_buf();
-        self.queued_paths.insert(
-            key,
-            (
-                archive_path.as_ref().to_path_buf(),
-                Some(compression_method),
-            ),
-        );
+        self.queue(
+            archive_path,
+            EntrySource::Path(disk_path.as_ref().to_path_buf()),
+            Some(compression_method),
+        )
+    }
+
+    /// Queue in-memory metadata and data to be added to the archive using the
+    /// default compression strategy of `archive_path`'s extension.
+    ///
+    /// Empty metadata stores the entry without metadata, see [`HpakWriter::with_default_meta`].
+    pub fn add_bytes(
+        &mut self,
+        archive_path: impl AsRef<Path>,
+        meta: impl Into<Vec<u8>>,
+        data: impl Into<Vec<u8>>,
+    ) -> &mut Self {
+        self.queue(
+            archive_path,
+            EntrySource::Bytes {
+                meta: meta.into(),
+                data: data.into(),
+            },
+            None,
+        )
+    }
+
+    /// Queue in-memory metadata and data with an explicit compression method for the data.
+    pub fn add_bytes_with(
+        &mut self,
+        archive_path: impl AsRef<Path>,
+        meta: impl Into<Vec<u8>>,
+        data: impl Into<Vec<u8>>,
+        compression_method: CompressionMethod,
+    ) -> &mut Self {
+        self.queue(
+            archive_path,
+            EntrySource::Bytes {
+                meta: meta.into(),
+                data: data.into(),
+            },
+            Some(compression_method),
+        )
+    }
+
+    /// Queue metadata and data read from arbitrary readers to be added to the archive
+    /// using the default compression strategy of `archive_path`'s extension.
+    ///
+    /// The readers are only consumed by [`HpakWriter::build`], where the data is
+    /// read in memory before being compressed.
+    /// Empty metadata stores the entry without metadata, see [`HpakWriter::with_default_meta`].
+    pub fn add_reader(
+        &mut self,
+        archive_path: impl AsRef<Path>,
+        meta: impl Read + Send + 'static,
+        data: impl Read + Send + 'static,
+    ) -> &mut Self {
+        self.queue(
+            archive_path,
+            EntrySource::Reader(Mutex::new((Box::new(meta), Box::new(data)))),
+            None,
+        )
+    }
+
+    /// Queue metadata and data read from arbitrary readers with an explicit
+    /// compression method for the data.
+    pub fn add_reader_with(
+        &mut self,
+        archive_path: impl AsRef<Path>,
+        meta: impl Read + Send + 'static,
+        data: impl Read + Send + 'static,
+        compression_method: CompressionMethod,
+    ) -> &mut Self {
+        self.queue(
+            archive_path,
+            EntrySource::Reader(Mutex::new((Box::new(meta), Box::new(data)))),
+            Some(compression_method),
+        )
+    }
+
+    fn queue(
+        &mut self,
+        archive_path: impl AsRef<Path>,
+        source: EntrySource,
+        compression_method: Option<CompressionMethod>,
+    ) -> &mut Self {
+        self.queued.push(QueuedEntry {
+            archive_path: archive_path.as_ref().to_path_buf(),
+            source,
+            compression_method,
+        });
+        self
+    }
+
+    /// Queue every file of `archive` with its metadata and compression method, and
+    /// mark the files it deletes as deleted.
+    ///
+    /// The files are only read by [`HpakWriter::build`], where they are decompressed
+    /// in memory before being compressed again.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a file of `archive` cannot be read.
+    pub fn add_archive(&mut self, archive: &HpakReader) -> Result<&mut Self> {
+        for file in archive.iter_files() {
+            let meta: Box<dyn Read + Send> = match archive.read_meta(file.path()) {
+                Ok(meta) => Box::new(BlockOn::new(meta)),
+                Err(Error::MetaNotFound(_)) => Box::new(std::io::empty()),
+                Err(err) => return Err(err),
+            };
+            let data = BlockOn::new(archive.read_data(file.path())?);
+
+            self.add_reader_with(file.path(), meta, data, file.compression_method());
+        }
+
+        for path in archive.iter_tombstones() {
+            self.add_tombstone(path);
+        }
+
+        Ok(self)
+    }
+
+    /// Queue every file of `archive` to be copied as stored, and mark the files it
+    /// deletes as deleted.
+    ///
+    /// Unlike [`HpakWriter::add_archive`], the compressed metadata and data of the
+    /// files are copied without being compressed again, keeping their compression
+    /// method and blocks. The metadata compression of the writer must match the one
+    /// of `archive`.
+    fn copy_archive(&mut self, archive: HpakReader) -> &mut Self {
+        let archive = Arc::new(archive);
+
+        for file in archive.iter_files() {
+            self.queue(
+                file.path(),
+                EntrySource::Archive(archive.clone()),
+                Some(file.compression_method()),
+            );
+        }
+
+        for path in archive.iter_tombstones() {
+            self.add_tombstone(path);
+        }
+
+        self
+    }
+
+    /// Mark the file at `archive_path` as deleted.
+    ///
+    /// The archive doesn't contain the file, but hides it in the archives mounted
+    /// below it in a [`HpakLayeredReader`](crate::HpakLayeredReader), which makes it
+    /// possible to remove files from a base archive with a patch archive.
+    pub fn add_tombstone(&mut self, archive_path: impl AsRef<Path>) -> &mut Self {
+        self.tombstones.insert(archive_path.as_ref().to_path_buf());
         self
     }
 
@@ -174,163 +492,462 @@ impl HpakWriter {
     /// - The path is not a directory
     /// - Files cannot be read during traversal
     pub fn add_paths_from_dir(&mut self, dir: impl AsRef<Path>) -> Result<&mut Self> {
-        let dir = dir.as_ref();
-
-        if !dir.exists() {
-            return Err(Error::Io(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                format!("directory does not exist: {}", dir.display()),
-            )));
+        for (disk_path, archive_path) in dir_files(dir.as_ref())? {
+            self.add_path(disk_path, archive_path);
         }
 
-        if !dir.is_dir() {
-            return Err(Error::Io(std::io::Error::new(
-                std::io::ErrorKind::InvalidInput,
-                format!("path is not a directory: {}", dir.display()),
-            )));
-        }
-
-        for entry in walk_dir(dir)? {
-            if entry.extension().and_then(|e| e.to_str()).unwrap_or("") == "meta" {
-                continue;
-            }
+        Ok(self)
+    }
 
-            let archive_path = entry.clone();
-            let archive_path = match archive_path.strip_prefix(dir) {
-                Ok(path) => path,
-                Err(e) => {
-                    return Err(Error::Io(std::io::Error::new(
-                        std::io::ErrorKind::InvalidInput,
-                        format!(
-                            "failed to strip prefix '{}' from path '{}': {e}",
-                            dir.display(),
-                            archive_path.display()
-                        ),
-                    )));
+    /// Queue the differences between the asset trees `base_dir` and `dir` to build
+    /// a patch archive, meant to be mounted above the archive of `base_dir`.
+    ///
+    /// Files of `dir` missing from `base_dir`, or whose data or `.meta` file changed,
+    /// are added to the archive. Files of `base_dir` missing from `dir` are marked
+    /// as deleted, see [`HpakWriter::add_tombstone`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if either directory cannot be traversed, see
+    /// [`HpakWriter::add_paths_from_dir`], or if a file cannot be read.
+    pub fn add_patch_from_dirs(
+        &mut self,
+        base_dir: impl AsRef<Path>,
+        dir: impl AsRef<Path>,
+    ) -> Result<&mut Self> {
+        let mut base = dir_files(base_dir.as_ref())?
+            .into_iter()
+            .map(|(disk_path, archive_path)| (archive_path, disk_path))
+            .collect::<BTreeMap<_, _>>();
+
+        for (disk_path, archive_path) in dir_files(dir.as_ref())? {
+            match base.remove(&archive_path) {
+                Some(base_path) if same_file_contents(&base_path, &disk_path)? => {}
+                _ => {
+                    self.add_path(disk_path, archive_path);
                 }
-            };
+            }
+        }
 
-            self.add_path(entry, archive_path);
+        for archive_path in base.into_keys() {
+            self.add_tombstone(archive_path);
         }
 
         Ok(self)
     }
 
-    /// Build the archive by processing all queued files.
+    /// Build the archive by processing all queued entries.
     ///
-    /// This method compresses and writes all queued files to the archive,
-    /// then writes the entry table and finalizes the header. Once this is
-    /// called, the archive cannot be modified further.
+    /// This method compresses and writes all queued entries to the archive, the
+    /// files on disk in the order of their disk paths followed by the entries added
+    /// from memory in the order of their archive paths, then writes the entry table and finalizes
+    /// the header. Once this is called, the archive cannot be modified further.
     ///
     /// # Errors
     ///
     /// Returns an error if:
     /// - The archive has already been finalized
-    /// - Duplicate entry paths are detected
+    /// - A file is both added and marked as deleted
+    /// - Two different entry paths have the same hash
     /// - Files cannot be read or compressed
     /// - Writing to the archive fails
+    /// - The entry table is encrypted without an encryption key
     pub fn build(&mut self) -> Result<()> {
         if self.finalized {
             return Err(Error::AlreadyFinalized);
         }
 
+        if self.encrypt_entries && self.cipher.is_none() {
+            return Err(Error::InvalidKey);
+        }
+
+        let key_check = self.cipher.as_ref().map(ArchiveCipher::key_check);
+
         // Write dummy header, overwritten in finalize()
         let header = HpakHeader {
-            meta_compression_method: CompressionMethod::None,
+            version: crate::VERSION,
+            meta_compression_method: self.meta_compression,
             entries_offset: 0,
+            entries_size: 0,
+            entries_checksum: 0,
+            key_check,
+            entries_nonce: self.encrypt_entries.then_some([0; NONCE_SIZE]),
+            user_data: self.user_data.clone(),
+            signature: self.signer.as_ref().map(|_| [0; SIGNATURE_SIZE]),
         };
         header.encode(&mut self.output)?;
 
-        for (disk_path, (archive_path, compression_method)) in self.queued_paths.iter().by_ref() {
-            let ext = disk_path.extension().and_then(|e| e.to_str()).unwrap_or("");
-            let meta_path = meta_path_for(disk_path);
+        let jobs = self.resolve_jobs()?;
+        let threads = match self.threads {
+            0 => std::thread::available_parallelism().map_or(1, |threads| threads.get()),
+            threads => threads,
+        };
 
-            let meta = File::open(&meta_path).map_err(|e| {
-                Error::Io(std::io::Error::new(
-                    e.kind(),
-                    format!(
-                        "failed to open metadata file '{}': {e}",
-                        meta_path.display()
-                    ),
-                ))
-            })?;
-            let data = File::open(disk_path).map_err(|e| {
-                Error::Io(std::io::Error::new(
-                    e.kind(),
-                    format!("failed to open data file '{}': {e}", disk_path.display()),
-                ))
-            })?;
-
-            let compression_method = compression_method.unwrap_or_else(|| {
+        // compress a bounded number of entries at a time to cap memory usage, entries
+        // are always written in queue order so the output doesn't depend on scheduling
+        for batch in jobs.chunks(threads * 4) {
+            for (job, compressed) in batch.iter().zip(self.compress_batch(batch, threads)) {
+                self.write_entry(job, compressed?)?;
+            }
+        }
+
+        self.finalize(key_check)
+    }
+
+    /// Take the queued entries and resolve their compression method, sorted by archive path.
+    ///
+    /// An entry queued again at the same archive path replaces the previous one.
+    fn resolve_jobs(&mut self) -> Result<Vec<Job>> {
+        let mut archive_paths = HashSet::new();
+        let mut queued = std::mem::take(&mut self.queued);
+        queued.reverse();
+        queued.retain(|entry| archive_paths.insert(entry.archive_path.clone()));
+        queued.sort_by(|a, b| a.sort_key().cmp(&b.sort_key()));
+
+        let mut jobs = Vec::with_capacity(queued.len());
+
+        for entry in queued {
+            let archive_path = entry.archive_path;
+
+            if self.entries.contains_key(&archive_path) || self.tombstones.contains(&archive_path) {
+                return Err(Error::DuplicateEntry(archive_path));
+            }
+
+            let ext = match &entry.source {
+                EntrySource::Path(disk_path) => disk_path.extension(),
+                _ => archive_path.extension(),
+            };
+            let ext = ext.and_then(|e| e.to_str()).unwrap_or("");
+
+            let compression_method = entry.compression_method.unwrap_or_else(|| {
                 self.default_compression_by_extension
                     .get(ext)
                     .copied()
-                    .unwrap_or(CompressionMethod::default())
+                    .unwrap_or(self.default_data_compression)
             });
 
-            let archive_path = archive_path.as_path();
+            jobs.push(Job {
+                archive_path,
+                source: entry.source,
+                compression_method,
+            });
+        }
 
-            if self.entries.contains_key(archive_path) {
-                return Err(Error::DuplicateEntry(archive_path.to_path_buf()));
+        Ok(jobs)
+    }
+
+    /// Compress every job of `batch` using up to `threads` threads, returning the
+    /// results in the same order as `batch`.
+    fn compress_batch(&self, batch: &[Job], threads: usize) -> Vec<Result<CompressedEntry>> {
+        if threads <= 1 || batch.len() <= 1 {
+            return batch.iter().map(|job| self.compress_entry(job)).collect();
+        }
+
+        let next = AtomicUsize::new(0);
+
+        let mut results = std::thread::scope(|scope| {
+            let workers = (0..threads.min(batch.len()))
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut results = Vec::new();
+
+                        loop {
+                            let index = next.fetch_add(1, Ordering::Relaxed);
+
+                            match batch.get(index) {
+                                Some(job) => results.push((index, self.compress_entry(job))),
+                                None => return results,
+                            }
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            workers
+                .into_iter()
+                .flat_map(|worker| {
+                    worker
+                        .join()
+                        .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+                })
+                .collect::<Vec<_>>()
+        });
+
+        results.sort_unstable_by_key(|(index, _)| *index);
+        results.into_iter().map(|(_, result)| result).collect()
+    }
+
+    /// Read and compress the metadata and data of `job` in memory.
+    fn compress_entry(&self, job: &Job) -> Result<CompressedEntry> {
+        match &job.source {
+            EntrySource::Path(disk_path) => {
+                let meta_path = meta_path_for(disk_path);
+
+                let meta = match File::open(&meta_path) {
+                    Ok(meta) => self.read_meta(meta)?,
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+                    Err(e) => {
+                        return Err(Error::Io(std::io::Error::new(
+                            e.kind(),
+                            format!(
+                                "failed to open metadata file '{}': {e}",
+                                meta_path.display()
+                            ),
+                        )));
+                    }
+                };
+                let data = File::open(disk_path).map_err(|e| {
+                    Error::Io(std::io::Error::new(
+                        e.kind(),
+                        format!("failed to open data file '{}': {e}", disk_path.display()),
+                    ))
+                })?;
+
+                self.compress_source(job, meta, data)
             }
+            EntrySource::Bytes { meta, data } => self.compress_source(
+                job,
+                self.read_meta(meta.as_slice())?,
+                Cursor::new(data.as_slice()),
+            ),
+            EntrySource::Reader(readers) => {
+                let mut readers = readers.lock();
+                let (meta, data) = &mut *readers;
 
-            if let Some(alignment) = self.alignment {
-                let offset = self.output.stream_position()?;
+                let meta = self.read_meta(meta)?;
+                let mut bytes = Vec::new();
+                data.read_to_end(&mut bytes)?;
 
-                let aligned = (offset + (alignment - 1)) & !(alignment - 1);
-                let padding = aligned - offset;
+                self.compress_source(job, meta, Cursor::new(bytes))
+            }
+            EntrySource::Archive(archive) => copy_entry(archive, &job.archive_path),
+        }
+    }
 
-                if padding > 0 {
-                    let padding_bytes = vec![0u8; padding as usize];
-                    self.output.write_all(&padding_bytes)?;
-                }
+    /// Read the metadata from `meta`, minifying it if enabled.
+    fn read_meta(&self, meta: impl Read) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
 
-                self.output.flush()?;
-            };
+        if self.minify_metadata {
+            RonMinifier::new(meta).read_to_end(&mut bytes)?;
+        } else {
+            let mut meta = meta;
+            meta.read_to_end(&mut bytes)?;
+        }
 
-            let meta_offset = self.output.stream_position()?;
-            let meta_size = self.meta_compression.compress(
-                if self.minify_metadata {
-                    Box::new(RonMinifier::new(meta)) as Box<dyn Read>
-                } else {
-                    Box::new(meta) as Box<dyn Read>
-                },
-                &mut self.output,
+        Ok(bytes)
+    }
+
+    /// Compress the metadata and data of `job`, or reuse them from the previous archive.
+    fn compress_source<D: Read + Seek>(
+        &self,
+        job: &Job,
+        mut meta_source: Vec<u8>,
+        mut data: D,
+    ) -> Result<CompressedEntry> {
+        if meta_source.is_empty()
+            && let Some(default_meta) = &self.default_meta
+            && let Some(meta) = (default_meta.0)(&job.archive_path)
+        {
+            meta_source = self.read_meta(meta.as_slice())?;
+        }
+
+        let meta_checksum = xxh3_64(&meta_source);
+
+        if let Some(compressed) = self.reuse_previous_entry(job, meta_checksum, &mut data)? {
+            return Ok(compressed);
+        }
+
+        // entries without metadata are stored with an empty metadata block
+        let mut meta_bytes = Vec::new();
+        if !meta_source.is_empty() {
+            self.meta_compression.compress(
+                self.zlib_encoder,
+                meta_source.as_slice(),
+                &mut meta_bytes,
             )?;
-            let data_size = compression_method.compress(data, &mut self.output)?;
+        }
 
-            let entry = HpakFileEntry {
-                hash: hash_path(archive_path),
-                compression_method,
-                meta_offset,
-                meta_size,
-                data_size,
-            };
+        let mut data = HashingReader::new(data);
+        let mut data_bytes = Vec::new();
+        let block_size = self.block_size_for(job.compression_method);
+        let mut blocks = match block_size {
+            0 => {
+                job.compression_method
+                    .compress(self.zlib_encoder, &mut data, &mut data_bytes)?;
+                Vec::new()
+            }
+            block_size => compress_blocks(
+                job.compression_method,
+                self.zlib_encoder,
+                block_size,
+                &mut data,
+                &mut data_bytes,
+            )?,
+        };
+
+        let nonce = match &self.cipher {
+            Some(cipher) => Some(cipher.encrypt_entry(
+                &job.archive_path,
+                data.bytes_read(),
+                &mut meta_bytes,
+                &mut data_bytes,
+                &mut blocks,
+            )?),
+            None => None,
+        };
+
+        Ok(CompressedEntry {
+            meta: meta_bytes,
+            meta_checksum,
+            data: data_bytes,
+            uncompressed_size: data.bytes_read(),
+            block_size,
+            blocks,
+            data_checksum: data.digest(),
+            nonce,
+        })
+    }
+
+    /// Copy the compressed bytes of `job` from the previous archive if its content
+    /// and compression settings are unchanged.
+    ///
+    /// `data` is hashed to compare it with the previous entry, it is rewound
+    /// if the entry cannot be reused.
+    fn reuse_previous_entry<D: Read + Seek>(
+        &self,
+        job: &Job,
+        meta_checksum: u64,
+        data: &mut D,
+    ) -> Result<Option<CompressedEntry>> {
+        let Some(previous) = &self.previous else {
+            return Ok(None);
+        };
+
+        let Ok(entry) = previous.get_entry(&job.archive_path) else {
+            return Ok(None);
+        };
+
+        // encrypted entries can only be reused if encrypted with the same key
+        let same_key = match (&self.cipher, previous.key_check()) {
+            (None, None) => true,
+            (Some(cipher), Some(key_check)) => cipher.check_key(key_check),
+            _ => false,
+        };
 
-            self.entries.insert(archive_path.to_path_buf(), entry);
+        if !same_key
+            || entry.legacy
+            || previous.meta_compression_method() != self.meta_compression
+            || entry.compression_method != job.compression_method
+            || entry.block_size != self.block_size_for(job.compression_method)
+            || entry.meta_checksum != meta_checksum
+        {
+            return Ok(None);
         }
 
-        self.finalize()
+        let mut hashing = HashingReader::new(&mut *data);
+        std::io::copy(&mut hashing, &mut std::io::sink())?;
+
+        let data_offset = entry.meta_offset + entry.meta_size;
+        let bytes = previous
+            .raw_bytes(entry.meta_offset, entry.meta_size)
+            .zip(previous.raw_bytes(data_offset, entry.data_size));
+
+        match bytes {
+            Some((meta, data))
+                if hashing.digest() == entry.data_checksum
+                    && hashing.bytes_read() == entry.uncompressed_size =>
+            {
+                Ok(Some(CompressedEntry {
+                    meta: meta.to_vec(),
+                    meta_checksum,
+                    data: data.to_vec(),
+                    uncompressed_size: entry.uncompressed_size,
+                    block_size: entry.block_size,
+                    blocks: entry.blocks.clone(),
+                    data_checksum: entry.data_checksum,
+                    nonce: entry.nonce,
+                }))
+            }
+            _ => {
+                data.seek(SeekFrom::Start(0))?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Returns the block size used for data compressed with `method`, `0` if not split into blocks.
+    fn block_size_for(&self, method: CompressionMethod) -> u64 {
+        match self.block_size {
+            Some(block_size) if method != CompressionMethod::None || self.cipher.is_some() => {
+                block_size
+            }
+            _ => 0,
+        }
+    }
+
+    /// Write a compressed entry at the end of the archive and record it.
+    fn write_entry(&mut self, job: &Job, compressed: CompressedEntry) -> Result<()> {
+        if let Some(alignment) = self.alignment {
+            let offset = self.output.stream_position()?;
+
+            let aligned = (offset + (alignment - 1)) & !(alignment - 1);
+            let padding = aligned - offset;
+
+            if padding > 0 {
+                let padding_bytes = vec![0u8; padding as usize];
+                self.output.write_all(&padding_bytes)?;
+            }
+        };
+
+        let meta_offset = self.output.stream_position()?;
+        self.output.write_all(&compressed.meta)?;
+        self.output.write_all(&compressed.data)?;
+
+        let entry = HpakFileEntry {
+            hash: hash_path(&job.archive_path),
+            path: job.archive_path.clone(),
+            compression_method: job.compression_method,
+            meta_offset,
+            meta_size: compressed.meta.len() as u64,
+            data_size: compressed.data.len() as u64,
+            uncompressed_size: compressed.uncompressed_size,
+            block_size: compressed.block_size,
+            blocks: compressed.blocks,
+            meta_checksum: compressed.meta_checksum,
+            data_checksum: compressed.data_checksum,
+            nonce: compressed.nonce,
+            legacy: false,
+        };
+
+        self.entries.insert(job.archive_path.clone(), entry);
+
+        Ok(())
     }
 
     /// Write the entries table and the final header then flush the writer.
-    fn finalize(&mut self) -> Result<()> {
+    fn finalize(&mut self, key_check: Option<[u8; KEY_CHECK_SIZE]>) -> Result<()> {
         if self.finalized {
             return Ok(());
         }
 
         self.finalized = true;
 
-        let header = HpakHeader {
-            meta_compression_method: self.meta_compression,
-            entries_offset: self.output.stream_position()?,
-        };
+        let entries_offset = self.output.stream_position()?;
 
         let mut entries = HpakEntries {
             directories: HashTable::new(),
             files: HashTable::new(),
+            tombstones: HashTable::new(),
         };
 
+        for path in &self.tombstones {
+            entries.insert_tombstone(path.clone())?;
+        }
+
+        // directory entries only store a hash, keep track of their paths to detect collisions
+        let mut directory_paths = HashMap::<u64, PathBuf>::new();
+
         // build directory/files tables
         for (path, entry) in self.entries.iter() {
             let mut ancestors = path.ancestors();
@@ -341,6 +958,14 @@ impl HpakWriter {
                 let ancestor_hash = hash_path(ancestor);
                 let ancestor: PathBuf = ancestor.into();
 
+                let known = directory_paths
+                    .entry(ancestor_hash)
+                    .or_insert_with(|| ancestor.clone());
+
+                if *known != ancestor {
+                    return Err(Error::HashCollision(known.clone(), ancestor));
+                }
+
                 let entry = entries
                     .directories
                     .entry(
@@ -363,16 +988,45 @@ impl HpakWriter {
             }
 
             // add the file entry to the file table
-            let path = hash_path(path.as_path());
-            entries
-                .files
-                .insert_unique(path, entry.clone(), HpakFileEntry::hash);
+            entries.insert_file(entry.clone())?;
         }
 
-        entries.encode(&mut self.output)?;
+        let mut table = Vec::new();
+        entries.encode(&mut table)?;
+
+        let entries_nonce = match &self.cipher {
+            Some(cipher) if self.encrypt_entries => {
+                let (encrypted, nonce) = cipher.encrypt_entries(&table)?;
+                table = encrypted;
+                Some(nonce)
+            }
+            _ => None,
+        };
+
+        self.output.write_all(&table)?;
 
         self.output.flush()?;
 
+        let mut header = HpakHeader {
+            version: crate::VERSION,
+            meta_compression_method: self.meta_compression,
+            entries_offset,
+            entries_size: table.len() as u64,
+            entries_checksum: xxh3_64(&table),
+            key_check,
+            entries_nonce,
+            user_data: self.user_data.clone(),
+            signature: None,
+        };
+
+        if let Some(signer) = &self.signer {
+            header.signature = Some([0; SIGNATURE_SIZE]);
+
+            let mut bytes = Vec::new();
+            header.encode(&mut bytes)?;
+            header.signature = Some(signer.sign(&bytes, &table));
+        }
+
         // return to the beginning of the file and overwrite dummy header
         self.output.seek(SeekFrom::Start(0))?;
         header.encode(&mut self.output)?;
@@ -611,6 +1265,80 @@ impl<R: Read> Read for RonMinifier<R> {
     }
 }
 
+/// Rewrite the archive at `from`, written with any supported format version, to
+/// the current version at `to`.
+///
+/// The compressed metadata and data of the files are copied as stored, files of
+/// archives written with a previous format version are only decompressed to compute
+/// their checksums and uncompressed size. Encrypted archives are not supported.
+///
+/// # Errors
+///
+/// Returns an error if `from` cannot be read or if `to` cannot be written.
+pub fn migrate_archive(from: impl AsRef<Path>, to: impl AsRef<Path>) -> Result<()> {
+    let archive = HpakReader::new(from)?;
+
+    HpakWriter::new(to)?
+        .meta_compression(archive.meta_compression_method())
+        .copy_archive(archive)
+        .build()
+}
+
+/// Returns the compressed metadata and data of the file at `path` in `archive`,
+/// as stored.
+///
+/// Files of archives written with a previous format version are decompressed to
+/// compute their checksums and uncompressed size, which they don't store.
+fn copy_entry(archive: &HpakReader, path: &Path) -> Result<CompressedEntry> {
+    let entry = archive.get_entry(path)?;
+
+    let data_offset = entry
+        .meta_offset
+        .checked_add(entry.meta_size)
+        .ok_or(Error::InvalidFileFormat)?;
+    let meta = archive
+        .raw_bytes(entry.meta_offset, entry.meta_size)
+        .ok_or(Error::InvalidFileFormat)?;
+    let data = archive
+        .raw_bytes(data_offset, entry.data_size)
+        .ok_or(Error::InvalidFileFormat)?;
+
+    let (meta_checksum, data_checksum, uncompressed_size) = match entry.legacy {
+        false => (
+            entry.meta_checksum,
+            entry.data_checksum,
+            entry.uncompressed_size,
+        ),
+        true => {
+            let meta_checksum = match archive.read_meta(path) {
+                Ok(meta) => {
+                    let mut meta = HashingReader::new(BlockOn::new(meta));
+                    std::io::copy(&mut meta, &mut std::io::sink())?;
+                    meta.digest()
+                }
+                Err(Error::MetaNotFound(_)) => xxh3_64(&[]),
+                Err(err) => return Err(err),
+            };
+
+            let mut data = HashingReader::new(BlockOn::new(archive.read_data(path)?));
+            std::io::copy(&mut data, &mut std::io::sink())?;
+
+            (meta_checksum, data.digest(), data.bytes_read())
+        }
+    };
+
+    Ok(CompressedEntry {
+        meta: meta.to_vec(),
+        meta_checksum,
+        data: data.to_vec(),
+        uncompressed_size,
+        block_size: entry.block_size,
+        blocks: entry.blocks.clone(),
+        data_checksum,
+        nonce: None,
+    })
+}
+
 /// Populate `writer` with sensible compression defaults for common file extensions.
 pub fn set_default_extension_compression_methods(writer: &mut HpakWriter) {
     use CompressionMethod::*;
@@ -672,6 +1400,142 @@ pub fn set_default_extension_compression_methods(writer: &mut HpakWriter) {
     }
 }
 
+type DefaultMetaFn = dyn Fn(&Path) -> Option<Vec<u8>> + Send + Sync;
+
+/// Callback synthesizing the metadata of entries without any.
+struct DefaultMeta(Box<DefaultMetaFn>);
+
+#[cfg(feature = "debug-impls")]
+impl std::fmt::Debug for DefaultMeta {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("DefaultMeta")
+    }
+}
+
+/// An entry queued to be added to the archive.
+#[cfg_attr(feature = "debug-impls", derive(Debug))]
+struct QueuedEntry {
+    archive_path: PathBuf,
+    source: EntrySource,
+    compression_method: Option<CompressionMethod>,
+}
+
+impl QueuedEntry {
+    /// Returns the key ordering the entries in the archive: files on disk by their
+    /// disk path first, then in-memory entries by their archive path.
+    fn sort_key(&self) -> (bool, &Path) {
+        match &self.source {
+            EntrySource::Path(disk_path) => (false, disk_path),
+            _ => (true, &self.archive_path),
+        }
+    }
+}
+
+/// Source of the metadata and data of a queued entry.
+enum EntrySource {
+    /// A file on disk, with its metadata in the sibling `.meta` file.
+    Path(PathBuf),
+    /// In-memory metadata and data.
+    Bytes { meta: Vec<u8>, data: Vec<u8> },
+    /// Metadata and data readers, behind a mutex so entries can be compressed on any thread.
+    Reader(Mutex<(Box<dyn Read + Send>, Box<dyn Read + Send>)>),
+    /// The file at the same archive path in an archive, copied as stored.
+    Archive(Arc<HpakReader>),
+}
+
+#[cfg(feature = "debug-impls")]
+impl std::fmt::Debug for EntrySource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EntrySource::Path(path) => f.debug_tuple("Path").field(path).finish(),
+            EntrySource::Bytes { meta, data } => f
+                .debug_struct("Bytes")
+                .field("meta", &meta.len())
+                .field("data", &data.len())
+                .finish(),
+            EntrySource::Reader(_) => f.write_str("Reader"),
+            EntrySource::Archive(_) => f.write_str("Archive"),
+        }
+    }
+}
+
+/// A queued entry resolved to its compression method.
+struct Job {
+    archive_path: PathBuf,
+    source: EntrySource,
+    compression_method: CompressionMethod,
+}
+
+/// Compressed metadata and data of an entry, not yet written to the archive.
+struct CompressedEntry {
+    meta: Vec<u8>,
+    meta_checksum: u64,
+    data: Vec<u8>,
+    uncompressed_size: u64,
+    block_size: u64,
+    blocks: Vec<u64>,
+    data_checksum: u64,
+    nonce: Option<[u8; NONCE_SIZE]>,
+}
+
+/// Reader computing the XXH3 digest and length of everything read through it.
+struct HashingReader<R: Read> {
+    inner: R,
+    hasher: Xxh3,
+    bytes_read: u64,
+}
+
+impl<R: Read> HashingReader<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            hasher: Xxh3::new(),
+            bytes_read: 0,
+        }
+    }
+
+    fn digest(&self) -> u64 {
+        self.hasher.digest()
+    }
+
+    fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.bytes_read += n as u64;
+        Ok(n)
+    }
+}
+
+/// Compress `data` as independent blocks of `block_size` uncompressed bytes,
+/// returning the compressed size of each block.
+fn compress_blocks<R: Read, W: Write>(
+    method: CompressionMethod,
+    zlib: ZlibEncoder,
+    block_size: u64,
+    mut data: R,
+    mut writer: W,
+) -> Result<Vec<u64>> {
+    let mut blocks = Vec::new();
+    let mut buffer = Vec::new();
+
+    loop {
+        buffer.clear();
+        (&mut data).take(block_size).read_to_end(&mut buffer)?;
+
+        if buffer.is_empty() {
+            return Ok(blocks);
+        }
+
+        blocks.push(method.compress(zlib, buffer.as_slice(), &mut writer)?);
+    }
+}
+
 #[inline]
 fn meta_path_for(path: impl AsRef<Path>) -> PathBuf {
     let mut meta_path = path.as_ref().to_path_buf();
@@ -681,6 +1545,64 @@ fn meta_path_for(path: impl AsRef<Path>) -> PathBuf {
     meta_path
 }
 
+/// Returns the disk and archive paths of every file under `dir`, except `.meta` files.
+fn dir_files(dir: &Path) -> Result<Vec<(PathBuf, PathBuf)>> {
+    if !dir.exists() {
+        return Err(Error::Io(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("directory does not exist: {}", dir.display()),
+        )));
+    }
+
+    if !dir.is_dir() {
+        return Err(Error::Io(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("path is not a directory: {}", dir.display()),
+        )));
+    }
+
+    let mut files = Vec::new();
+
+    for entry in walk_dir(dir)? {
+        if entry.extension().and_then(|e| e.to_str()).unwrap_or("") == "meta" {
+            continue;
+        }
+
+        let archive_path = match entry.strip_prefix(dir) {
+            Ok(path) => path.to_path_buf(),
+            Err(e) => {
+                return Err(Error::Io(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!(
+                        "failed to strip prefix '{}' from path '{}': {e}",
+                        dir.display(),
+                        entry.display()
+                    ),
+                )));
+            }
+        };
+
+        files.push((entry, archive_path));
+    }
+
+    Ok(files)
+}
+
+/// Returns `true` if the files at `a` and `b` and their `.meta` files have the same contents.
+fn same_file_contents(a: &Path, b: &Path) -> Result<bool> {
+    fn read_meta(path: &Path) -> Result<Option<Vec<u8>>> {
+        match fs::read(meta_path_for(path)) {
+            Ok(meta) => Ok(Some(meta)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(Error::Io(e)),
+        }
+    }
+
+    Ok(fs::metadata(a)?.len() == fs::metadata(b)?.len()
+        && read_meta(a)? == read_meta(b)?
+        && fs::read(a)? == fs::read(b)?)
+}
+
 fn walk_dir<'a>(root: impl AsRef<Path>) -> Result<Box<dyn Iterator<Item = PathBuf> + 'a>> {
     let root_path = root.as_ref();
 
@@ -759,4 +1681,515 @@ mod tests {
         min.read_to_end(&mut out).unwrap();
         assert_eq!(output, String::from_utf8(out).unwrap());
     }
+
+    #[test]
+    fn it_build_identical_archives_regardless_of_threads() {
+        let dir = temp_dir("writer-threads");
+        let assets = dir.join("assets");
+        std::fs::create_dir_all(&assets).unwrap();
+
+        for i in 0..32 {
+            let name = format!("{i}.txt");
+            std::fs::write(assets.join(&name), name.repeat(i * 512 + 1)).unwrap();
+            std::fs::write(assets.join(format!("{name}.meta")), format!("({i})")).unwrap();
+        }
+
+        let archives = [1, 3, 8].map(|threads| {
+            let archive = dir.join(format!("{threads}.hpak"));
+
+            HpakWriter::new(&archive)
+                .unwrap()
+                .default_data_compression(CompressionMethod::Zstd(3))
+                .with_alignment(64)
+                .with_block_size(1024)
+                .with_threads(threads)
+                .add_paths_from_dir(&assets)
+                .unwrap()
+                .build()
+                .unwrap();
+
+            std::fs::read(archive).unwrap()
+        });
+
+        assert!(archives.iter().all(|archive| *archive == archives[0]));
+
+        let reader = HpakReader::new(dir.join("8.hpak")).unwrap();
+        assert_eq!(32, reader.iter_files().count());
+        assert!(
+            reader
+                .iter_files()
+                .all(|entry| entry.compression_method() == CompressionMethod::Zstd(3))
+        );
+    }
+
+    #[test]
+    fn it_fall_back_to_default_data_compression() {
+        let dir = temp_dir("writer-default-compression");
+
+        for (name, default, expected) in [
+            ("default.hpak", None, CompressionMethod::default()),
+            (
+                "zstd.hpak",
+                Some(CompressionMethod::Zstd(3)),
+                CompressionMethod::Zstd(3),
+            ),
+        ] {
+            let archive = write_archive(&dir, name, |writer| {
+                if let Some(method) = default {
+                    writer.default_data_compression(method);
+                }
+
+                writer
+                    .default_compression_for_extension("png", CompressionMethod::Lz4)
+                    .add_bytes("a.txt", "", "a")
+                    .add_bytes("b.png", "", "b")
+                    .add_bytes_with("c.txt", "", "c", CompressionMethod::Zlib);
+            });
+
+            let reader = HpakReader::new(archive).unwrap();
+            let method = |path: &str| {
+                reader
+                    .get_entry(Path::new(path))
+                    .unwrap()
+                    .compression_method()
+            };
+
+            assert_eq!(expected, method("a.txt"));
+            assert_eq!(CompressionMethod::Lz4, method("b.png"));
+            assert_eq!(CompressionMethod::Zlib, method("c.txt"));
+        }
+    }
+
+    #[test]
+    fn it_write_entries_in_disk_path_order() {
+        let dir = temp_dir("writer-order");
+
+        for name in ["a.txt", "b.txt"] {
+            std::fs::write(dir.join(name), name).unwrap();
+        }
+
+        let archive = write_archive(&dir, "assets.hpak", |writer| {
+            writer
+                .add_bytes("x.txt", "", "x")
+                .add_path(dir.join("b.txt"), "y.txt")
+                .add_bytes("w.txt", "", "w")
+                .add_path(dir.join("a.txt"), "z.txt");
+        });
+
+        let reader = HpakReader::new(archive).unwrap();
+        let mut entries = reader.iter_files().collect::<Vec<_>>();
+        entries.sort_by_key(|entry| entry.meta_offset);
+
+        let paths = entries
+            .iter()
+            .map(|entry| entry.path().to_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(vec!["z.txt", "y.txt", "w.txt", "x.txt"], paths);
+    }
+
+    #[test]
+    fn it_reuse_unchanged_entries_from_previous_archive() {
+        let dir = temp_dir("writer-previous");
+        let assets = dir.join("assets");
+        std::fs::create_dir_all(&assets).unwrap();
+
+        for name in ["a.txt", "b.txt", "c.txt"] {
+            std::fs::write(assets.join(name), name.repeat(4096)).unwrap();
+            std::fs::write(assets.join(format!("{name}.meta")), "()").unwrap();
+        }
+
+        let build = |archive: &Path, level: u32, previous: Option<&Path>| {
+            let mut writer = HpakWriter::new(archive).unwrap();
+
+            if let Some(previous) = previous {
+                writer.with_previous_archive(previous).unwrap();
+            }
+
+            writer
+                .default_data_compression(CompressionMethod::Zlib)
+                .zlib_encoder(ZlibEncoder::Flate2(level))
+                .add_paths_from_dir(&assets)
+                .unwrap()
+                .build()
+                .unwrap();
+
+            HpakReader::new(archive).unwrap()
+        };
+
+        let previous = build(&dir.join("previous.hpak"), 9, None);
+
+        std::fs::write(assets.join("b.txt"), "changed").unwrap();
+        std::fs::write(assets.join("c.txt.meta"), "(changed: true)").unwrap();
+
+        // entries compressed again are stored uncompressed, reused ones keep their size
+        let current = build(
+            &dir.join("current.hpak"),
+            0,
+            Some(&dir.join("previous.hpak")),
+        );
+
+        let size = |reader: &HpakReader, path: &str| {
+            reader.get_entry(Path::new(path)).unwrap().compressed_size()
+        };
+
+        assert_eq!(size(&previous, "a.txt"), size(&current, "a.txt"));
+        assert!(size(&current, "c.txt") > size(&previous, "c.txt"));
+        assert_eq!(7, current.uncompressed_size(Path::new("b.txt")).unwrap());
+        assert!(current.verify().unwrap().is_ok());
+    }
+
+    #[test]
+    fn it_add_bytes_and_readers() {
+        let dir = temp_dir("writer-bytes");
+        let archive = dir.join("assets.hpak");
+
+        HpakWriter::new(&archive)
+            .unwrap()
+            .add_bytes("lightmaps/a.bin", "(lightmap: 0)", vec![1u8; 4096])
+            .add_bytes_with(
+                "locale/en.ftl",
+                "()",
+                "hello = Hello",
+                CompressionMethod::Lz4,
+            )
+            .add_reader(
+                "locale/fr.ftl",
+                Cursor::new("()"),
+                Cursor::new("hello = Bonjour"),
+            )
+            .build()
+            .unwrap();
+
+        let reader = HpakReader::new(&archive)
+            .unwrap()
+            .with_checksum_verification(true);
+
+        let read = |path: &str| {
+            let path = Path::new(path);
+            let mut data = Vec::new();
+            let mut meta = Vec::new();
+
+            futures_lite::future::block_on(async {
+                bevy::asset::io::Reader::read_to_end(
+                    &mut reader.read_data(path).unwrap(),
+                    &mut data,
+                )
+                .await
+                .unwrap();
+                bevy::asset::io::Reader::read_to_end(
+                    &mut reader.read_meta(path).unwrap(),
+                    &mut meta,
+                )
+                .await
+                .unwrap();
+            });
+
+            (String::from_utf8(meta).unwrap(), data)
+        };
+
+        assert_eq!(
+            ("(lightmap:0)".into(), vec![1u8; 4096]),
+            read("lightmaps/a.bin")
+        );
+        assert_eq!(
+            ("()".into(), b"hello = Hello".to_vec()),
+            read("locale/en.ftl")
+        );
+        assert_eq!(
+            ("()".into(), b"hello = Bonjour".to_vec()),
+            read("locale/fr.ftl")
+        );
+        assert_eq!(
+            CompressionMethod::Lz4,
+            reader
+                .get_entry(Path::new("locale/en.ftl"))
+                .unwrap()
+                .compression_method()
+        );
+    }
+
+    #[test]
+    fn it_pack_with_oversized_block_size() {
+        let dir = temp_dir("writer-oversized-block");
+        let archive = write_archive(&dir, "assets.hpak", |writer| {
+            writer.with_block_size(u64::MAX).add_bytes_with(
+                "a.bin",
+                "()",
+                vec![1u8; 4096],
+                CompressionMethod::Zlib,
+            );
+        });
+
+        let reader = HpakReader::new(&archive).unwrap();
+        let entry = reader.get_entry(Path::new("a.bin")).unwrap();
+        assert_eq!(1, entry.blocks.len());
+        assert!(reader.verify().unwrap().is_ok());
+    }
+
+    #[test]
+    fn it_pack_entries_without_meta() {
+        use bevy::asset::io::{AssetReader, AssetReaderError};
+
+        let dir = temp_dir("writer-without-meta");
+        let assets = dir.join("assets");
+        std::fs::create_dir_all(&assets).unwrap();
+
+        std::fs::write(assets.join("a.txt"), "a").unwrap();
+        std::fs::write(assets.join("a.txt.meta"), "(a)").unwrap();
+        std::fs::write(assets.join("b.txt"), "b").unwrap();
+        std::fs::write(assets.join("c.ron"), "c").unwrap();
+
+        let archive = dir.join("assets.hpak");
+        HpakWriter::new(&archive)
+            .unwrap()
+            .meta_compression(CompressionMethod::Zstd(0))
+            .with_default_meta(|path| {
+                (path.extension()? == "ron").then(|| b"(default: true)".to_vec())
+            })
+            .add_paths_from_dir(&assets)
+            .unwrap()
+            .add_bytes("d.txt", Vec::new(), "d")
+            .build()
+            .unwrap();
+
+        let reader = HpakReader::new(&archive).unwrap();
+        let read_meta =
+            |path: &str| futures_lite::future::block_on(reader.read_meta_bytes(Path::new(path)));
+
+        assert_eq!(b"(a)".to_vec(), read_meta("a.txt").unwrap());
+        assert_eq!(b"(default:true)".to_vec(), read_meta("c.ron").unwrap());
+
+        for path in ["b.txt", "d.txt"] {
+            assert!(matches!(
+                read_meta(path),
+                Err(AssetReaderError::NotFound(_))
+            ));
+            assert!(matches!(
+                reader.read_meta(Path::new(path)),
+                Err(Error::MetaNotFound(_))
+            ));
+        }
+
+        assert!(reader.verify().unwrap().is_ok());
+    }
+
+    #[test]
+    fn it_diff_asset_trees_into_a_patch() {
+        let dir = temp_dir("writer-patch");
+        let base = dir.join("base");
+        let assets = dir.join("assets");
+        std::fs::create_dir_all(base.join("sub")).unwrap();
+        std::fs::create_dir_all(assets.join("sub")).unwrap();
+
+        for root in [&base, &assets] {
+            std::fs::write(root.join("same.txt"), "same").unwrap();
+            std::fs::write(root.join("same.txt.meta"), "()").unwrap();
+            std::fs::write(root.join("sub/data.txt"), "data").unwrap();
+        }
+
+        std::fs::write(base.join("sub/meta.txt"), "meta").unwrap();
+        std::fs::write(assets.join("sub/meta.txt"), "meta").unwrap();
+        std::fs::write(assets.join("sub/meta.txt.meta"), "(new: true)").unwrap();
+        std::fs::write(base.join("sub/data.txt.meta"), "()").unwrap();
+        std::fs::write(assets.join("sub/data.txt.meta"), "()").unwrap();
+        std::fs::write(assets.join("sub/data.txt"), "changed").unwrap();
+        std::fs::write(base.join("deleted.txt"), "deleted").unwrap();
+        std::fs::write(assets.join("added.txt"), "added").unwrap();
+
+        let archive = dir.join("patch.hpak");
+        HpakWriter::new(&archive)
+            .unwrap()
+            .add_patch_from_dirs(&base, &assets)
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let reader = HpakReader::new(&archive).unwrap();
+
+        let mut files = reader
+            .iter_files()
+            .map(|file| file.path().to_path_buf())
+            .collect::<Vec<_>>();
+        files.sort();
+
+        assert_eq!(
+            vec![
+                PathBuf::from("added.txt"),
+                PathBuf::from("sub/data.txt"),
+                PathBuf::from("sub/meta.txt")
+            ],
+            files
+        );
+        assert_eq!(
+            vec![Path::new("deleted.txt")],
+            reader.iter_tombstones().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn it_override_queued_entries() {
+        let dir = temp_dir("writer-override");
+        let assets = dir.join("assets");
+        std::fs::create_dir_all(&assets).unwrap();
+        std::fs::write(assets.join("a.txt"), "a".repeat(64)).unwrap();
+        std::fs::write(assets.join("b.txt"), "b".repeat(64)).unwrap();
+
+        let archive = write_archive(&dir, "assets.hpak", |writer| {
+            writer
+                .add_paths_from_dir(&assets)
+                .unwrap()
+                .add_path_with(assets.join("a.txt"), "a.txt", CompressionMethod::Zlib)
+                .add_bytes("b.txt", "()", "c")
+                .add_bytes("c.txt", "()", "c");
+        });
+
+        let reader = HpakReader::new(&archive).unwrap();
+        assert_eq!(3, reader.iter_files().count());
+        assert_eq!(
+            CompressionMethod::Zlib,
+            reader
+                .get_entry(Path::new("a.txt"))
+                .unwrap()
+                .compression_method()
+        );
+        assert_eq!(1, reader.uncompressed_size(Path::new("b.txt")).unwrap());
+        assert!(reader.verify().unwrap().is_ok());
+    }
+
+    #[test]
+    fn it_reject_tombstones_of_added_entries() {
+        let dir = temp_dir("writer-tombstone-duplicate");
+
+        let result = HpakWriter::new(dir.join("patch.hpak"))
+            .unwrap()
+            .add_bytes("a.txt", "()", "a")
+            .add_tombstone("a.txt")
+            .build();
+
+        assert!(matches!(result, Err(Error::DuplicateEntry(path)) if path == Path::new("a.txt")));
+    }
+
+    /// Returns the metadata and data of the file at `path` as stored in `archive`.
+    fn stored_bytes(archive: &HpakReader, path: &Path) -> (Vec<u8>, Vec<u8>) {
+        let entry = archive.get_entry(path).unwrap();
+        let data_offset = entry.meta_offset + entry.meta_size;
+
+        (
+            archive
+                .raw_bytes(entry.meta_offset, entry.meta_size)
+                .unwrap()
+                .to_vec(),
+            archive
+                .raw_bytes(data_offset, entry.data_size)
+                .unwrap()
+                .to_vec(),
+        )
+    }
+
+    #[test]
+    fn it_migrate_archive_as_stored() {
+        let dir = temp_dir("writer-migrate-current");
+        let migrated = dir.join("migrated.hpak");
+        let archive = write_archive(&dir, "assets.hpak", |writer| {
+            writer
+                .meta_compression(CompressionMethod::Zlib)
+                .minify_metadata(false)
+                .zlib_encoder(ZlibEncoder::Flate2(1))
+                .with_block_size(1024)
+                .add_bytes_with(
+                    "a.txt",
+                    "( a : 1 )",
+                    "a".repeat(4096),
+                    CompressionMethod::Zlib,
+                )
+                .add_bytes_with("b.txt", Vec::new(), "b", CompressionMethod::None)
+                .add_tombstone("c.txt");
+        });
+
+        migrate_archive(&archive, &migrated).unwrap();
+
+        let source = HpakReader::new(&archive).unwrap();
+        let reader = HpakReader::new(&migrated).unwrap();
+        assert!(reader.verify().unwrap().is_ok());
+        assert_eq!(
+            vec![Path::new("c.txt")],
+            reader.iter_tombstones().collect::<Vec<_>>()
+        );
+
+        for file in source.iter_files() {
+            let path = file.path();
+            let entry = reader.get_entry(path).unwrap();
+
+            assert_eq!(stored_bytes(&source, path), stored_bytes(&reader, path));
+            assert_eq!(file.block_size, entry.block_size);
+            assert_eq!(file.blocks, entry.blocks);
+            assert_eq!(file.meta_checksum, entry.meta_checksum);
+            assert_eq!(file.data_checksum, entry.data_checksum);
+        }
+    }
+
+    #[test]
+    fn it_migrate_legacy_archive() {
+        use futures_lite::{AsyncReadExt, future::block_on};
+
+        let dir = temp_dir("writer-migrate");
+        let migrated = dir.join("migrated.hpak");
+        let data = "zlib b".repeat(64);
+
+        migrate_archive(legacy::v6_archive(), &migrated).unwrap();
+
+        let reader = HpakReader::new(&migrated)
+            .unwrap()
+            .with_checksum_verification(true);
+        assert_eq!(crate::VERSION, reader.version());
+
+        let mut paths = reader
+            .iter_files()
+            .map(|file| file.path().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        paths.sort();
+        assert_eq!(vec!["a.txt", "sub/b.txt"], paths);
+
+        let entry = reader.get_entry(Path::new("sub/b.txt")).unwrap();
+        assert_eq!(CompressionMethod::Zlib, entry.compression_method());
+        assert_eq!(data.len() as u64, entry.uncompressed_size());
+
+        // the compressed bytes are copied as stored
+        let legacy = HpakReader::new(legacy::v6_archive()).unwrap();
+        assert_eq!(
+            legacy.meta_compression_method(),
+            reader.meta_compression_method()
+        );
+        for file in legacy.iter_files() {
+            assert_eq!(
+                stored_bytes(&legacy, file.path()),
+                stored_bytes(&reader, file.path())
+            );
+        }
+
+        for (path, meta, expected) in [("a.txt", "(a:1)", "none a"), ("sub/b.txt", "(b:2)", &data)]
+        {
+            let path = Path::new(path);
+
+            block_on(async {
+                let mut bytes = String::new();
+                reader
+                    .read_meta(path)
+                    .unwrap()
+                    .read_to_string(&mut bytes)
+                    .await
+                    .unwrap();
+                assert_eq!(meta, bytes);
+
+                let mut bytes = String::new();
+                reader
+                    .read_data(path)
+                    .unwrap()
+                    .read_to_string(&mut bytes)
+                    .await
+                    .unwrap();
+                assert_eq!(expected, &bytes);
+            });
+        }
+    }
 }
@@ -10,12 +10,17 @@ mod format;
 use std::path::PathBuf;
 
 use bevy::{
-    asset::io::{AssetReaderError, AssetSource, AssetSourceBuilder, AssetSourceId},
+    asset::io::{
+        AssetReaderError, AssetSource, AssetSourceBuilder, AssetSourceId, ErasedAssetReader,
+    },
     prelude::*,
 };
 use thiserror::Error;
 
-pub use format::{CompressionMethod, HpakReader};
+pub use format::{
+    CompressionMethod, HpakFileEntry, HpakHybridReader, HpakLayeredReader, HpakReader, LookupOrder,
+    VerifyIssue, VerifyReport, verify_archive,
+};
 
 /// The magic number identifying HPAK files (ASCII "HPAK").
 ///
@@ -26,7 +31,13 @@ pub const MAGIC: [u8; 4] = *b"HPAK";
 /// The current version of the HPAK file format.
 ///
 /// This version number is stored in the archive header.
-pub const VERSION: u32 = 6;
+pub const VERSION: u32 = 7;
+
+/// The oldest version of the HPAK file format that can still be read.
+///
+/// Archives written with an older version than [`VERSION`] can be rewritten to
+/// the current version with `writer::migrate_archive`.
+pub const MIN_VERSION: u32 = 6;
 
 pub type Result<T> = core::result::Result<T, Error>;
 
@@ -36,8 +47,12 @@ pub enum Error {
     AlreadyFinalized,
     #[error("duplicated hpak entry: {0}")]
     DuplicateEntry(PathBuf),
+    #[error("hash collision between hpak entries: {0} and {1}")]
+    HashCollision(PathBuf, PathBuf),
     #[error("hpak entry not found: {0}")]
     EntryNotFound(PathBuf),
+    #[error("hpak entry has no metadata: {0}")]
+    MetaNotFound(PathBuf),
     #[error("invalid hpak file format")]
     InvalidFileFormat,
     #[error("bad hpak version: {0}")]
@@ -50,6 +65,20 @@ pub enum Error {
     InvalidAlignment(u64),
     #[error("encountered an invalid utf8 error: {0}")]
     InvalidUtf8(#[from] std::string::FromUtf8Error),
+    #[error("checksum mismatch for hpak entry: {0}")]
+    ChecksumMismatch(PathBuf),
+    #[error("hpak entries table checksum mismatch")]
+    EntriesChecksumMismatch,
+    #[error("missing or invalid key for the encrypted hpak archive")]
+    InvalidKey,
+    #[error("cannot decrypt hpak entry: {0}")]
+    DecryptionFailed(PathBuf),
+    #[error("cannot encrypt hpak archive, an entry exceeds the ChaCha20-Poly1305 size limit")]
+    EncryptionFailed,
+    #[error("missing or invalid signature of the hpak archive")]
+    InvalidSignature,
+    #[error("the hpak archive uses unsupported features: {0:#x}")]
+    UnsupportedFeatures(u64),
 }
 
 impl From<Error> for AssetReaderError {
@@ -57,8 +86,12 @@ impl From<Error> for AssetReaderError {
         use Error::*;
 
         match err {
-            EntryNotFound(path) => AssetReaderError::NotFound(path),
+            EntryNotFound(path) | MetaNotFound(path) => AssetReaderError::NotFound(path),
             Io(err) => AssetReaderError::Io(err.into()),
+            // keep the error retrievable from the io error, as when reading blocks
+            err @ (DecryptionFailed(_) | ChecksumMismatch(_)) => AssetReaderError::Io(
+                std::io::Error::new(std::io::ErrorKind::InvalidData, err).into(),
+            ),
             err => AssetReaderError::Io(std::io::Error::other(format!("{}", err)).into()),
         }
     }
@@ -83,6 +116,29 @@ pub enum HistrionPackerMode {
     /// **Important**: This plugin must be added **before** `AssetPlugin` in the plugin chain.
     #[default]
     ReplaceDefaultProcessed,
+
+    /// Replace the default [`AssetSource`] with the HPAK source for both unprocessed
+    /// and processed files.
+    ///
+    /// In this mode, every asset load of the default source is served from the HPAK
+    /// archive whatever the [`AssetMode`], so projects that don't use Bevy's asset
+    /// processor can ship their raw assets folder as a single archive.
+    ///
+    /// **Important**: This plugin must be added **before** `AssetPlugin` in the plugin chain.
+    ReplaceDefault,
+
+    /// Replace the default [`AssetSource`] with a [`HpakHybridReader`] for both unprocessed
+    /// and processed files, serving files missing from the HPAK archive from the filesystem
+    /// or the other way around.
+    ///
+    /// Each `(prefix, order)` pair sets the [`LookupOrder`] of the files under `prefix`,
+    /// the longest matching prefix wins and files matching no prefix are looked up in the
+    /// archive first. Unprocessed files fall back to the `assets` directory and processed
+    /// files to the `imported_assets/Default` directory. This is meant for development,
+    /// to iterate on some assets without repacking the archive.
+    ///
+    /// **Important**: This plugin must be added **before** `AssetPlugin` in the plugin chain.
+    Hybrid(Vec<(String, LookupOrder)>),
 }
 
 /// Bevy plugin for loading assets from HPAK archives.
@@ -90,6 +146,14 @@ pub enum HistrionPackerMode {
 /// This plugin integrates with Bevy's asset system to load assets from a packed HPAK archive
 /// instead of (or in addition to) loose files on disk.
 ///
+/// Additional archives, such as DLCs or hotfixes, can be mounted on top of the main one
+/// with [`HistrionPackerPlugin::with_layer`]. The plugin inserts the [`HpakLayeredReader`]
+/// serving the assets as a resource, so archives can also be mounted at runtime.
+///
+/// Archives are opened and validated when the plugin is built. An archive that is missing
+/// or corrupted is skipped, as if it was empty, and reported in the [`HpakMountErrors`]
+/// resource, e.g. to ask the player to reinstall the game.
+///
 /// # Examples
 ///
 /// ```no_run
@@ -100,10 +164,11 @@ pub enum HistrionPackerMode {
 ///     .add_plugins(
 ///         DefaultPlugins
 ///             .build()
-///             .add_before::<AssetPlugin>(HistrionPackerPlugin {
-///                 source: "assets.hpak".to_string(),
-///                 mode: HistrionPackerMode::ReplaceDefaultProcessed,
-///             })
+///             .add_before::<AssetPlugin>(
+///                 HistrionPackerPlugin::new("assets.hpak")
+///                     .with_layer("hotfix.hpak", 10)
+///                     .with_mode(HistrionPackerMode::ReplaceDefaultProcessed),
+///             )
 ///             .set(AssetPlugin {
 ///                 mode: AssetMode::Processed,
 ///                 ..default()
@@ -113,69 +178,257 @@ pub enum HistrionPackerMode {
 /// ```
 pub struct HistrionPackerPlugin {
     /// Path to the HPAK archive file, relative to the executable location.
-    pub source: String,
+    source: String,
+    /// Paths to additional HPAK archives with the priority they are mounted with.
+    layers: Vec<(String, i32)>,
+    mode: HistrionPackerMode,
+    #[cfg(feature = "encryption")]
+    encryption_key: Option<[u8; 32]>,
+    #[cfg(feature = "encryption")]
+    key_provider: Option<HpakKeyProvider>,
+    #[cfg(feature = "signing")]
+    public_key: Option<[u8; 32]>,
+}
+
+/// Callback returning the key of the encrypted archive at the given path, if any.
+#[cfg(feature = "encryption")]
+type HpakKeyProvider = std::sync::Arc<dyn Fn(&std::path::Path) -> Option<[u8; 32]> + Send + Sync>;
 
-    /// Integration mode determining how the plugin interacts with Bevy's asset system.
-    pub mode: HistrionPackerMode,
+impl Default for HistrionPackerPlugin {
+    fn default() -> Self {
+        Self {
+            source: "assets.hpak".to_string(),
+            layers: Vec::new(),
+            mode: HistrionPackerMode::default(),
+            #[cfg(feature = "encryption")]
+            encryption_key: None,
+            #[cfg(feature = "encryption")]
+            key_provider: None,
+            #[cfg(feature = "signing")]
+            public_key: None,
+        }
+    }
+}
+
+impl HistrionPackerPlugin {
+    /// Create a plugin serving the assets of the HPAK archive at `source`, relative to
+    /// the executable location, mounted with priority `0`.
+    pub fn new(source: impl Into<String>) -> Self {
+        Self {
+            source: source.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Mount the HPAK archive at `path`, relative to the executable location, on top of
+    /// the source archive with `priority`. Higher priorities override lower ones.
+    pub fn with_layer(mut self, path: impl Into<String>, priority: i32) -> Self {
+        self.layers.push((path.into(), priority));
+        self
+    }
+
+    /// Set the integration mode determining how the plugin interacts with Bevy's asset
+    /// system.
+    ///
+    /// Defaults to [`HistrionPackerMode::ReplaceDefaultProcessed`].
+    pub fn with_mode(mut self, mode: HistrionPackerMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Set the key decrypting the archives encrypted with `HpakWriter::with_encryption_key`.
+    ///
+    /// Archives that are not encrypted are mounted as is.
+    #[cfg(feature = "encryption")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "encryption")))]
+    pub fn with_encryption_key(mut self, key: [u8; 32]) -> Self {
+        self.encryption_key = Some(key);
+        self
+    }
+
+    /// Set the callback returning the key of each encrypted archive from its path, called
+    /// if no key is set with [`HistrionPackerPlugin::with_encryption_key`].
+    ///
+    /// This allows keeping the key out of the executable or using a key per archive.
+    #[cfg(feature = "encryption")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "encryption")))]
+    pub fn with_key_provider(
+        mut self,
+        provider: impl Fn(&std::path::Path) -> Option<[u8; 32]> + Send + Sync + 'static,
+    ) -> Self {
+        self.key_provider = Some(std::sync::Arc::new(provider));
+        self
+    }
+
+    /// Set the Ed25519 public key the archives must be signed with, see
+    /// `HpakWriter::with_signing_key`.
+    ///
+    /// Archives that are not signed with the matching secret key fail to mount with
+    /// [`Error::InvalidSignature`]. Each entry is then checked against its signed
+    /// checksum as it is read, see [`HpakReader::with_checksum_verification`], so
+    /// tampered entries fail to load once read to the end or seeked.
+    #[cfg(feature = "signing")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "signing")))]
+    pub fn with_public_key(mut self, public_key: [u8; 32]) -> Self {
+        self.public_key = Some(public_key);
+        self
+    }
+}
+
+/// An archive that [`HistrionPackerPlugin`] failed to mount.
+#[derive(Debug)]
+pub struct HpakMountError {
+    /// Path of the archive.
+    pub path: PathBuf,
+    /// Why the archive could not be mounted.
+    pub error: Error,
+}
+
+/// Resource inserted by [`HistrionPackerPlugin`] listing the archives it failed to mount.
+///
+/// # Examples
+///
+/// ```no_run
+/// use bevy::prelude::*;
+/// use bevy_histrion_packer::HpakMountErrors;
+///
+/// fn check_archives(errors: Res<HpakMountErrors>) {
+///     for error in errors.iter() {
+///         // show a "reinstall the game" dialog
+///         println!("{}: {}", error.path.display(), error.error);
+///     }
+/// }
+/// ```
+#[derive(Debug, Default, Resource)]
+pub struct HpakMountErrors(pub Vec<HpakMountError>);
+
+impl std::ops::Deref for HpakMountErrors {
+    type Target = Vec<HpakMountError>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
 }
 
 impl Plugin for HistrionPackerPlugin {
     fn build(&self, app: &mut bevy::prelude::App) {
-        let source = match std::env::current_exe() {
-            Ok(exe) => exe,
+        let base_path = match executable_dir() {
+            Ok(path) => path,
             Err(err) => {
-                error!("cannot get current executable path: {err}");
-                return;
+                error!("cannot get current executable directory: {err}");
+                PathBuf::new()
             }
         };
 
-        if !source.exists() {
-            error!("the source path does not exist or is not a file");
+        if !matches!(self.mode, HistrionPackerMode::Autoload(_))
+            && app.is_plugin_added::<AssetPlugin>()
+        {
+            error!("plugin HistrionPackerPlugin must be added before plugin AssetPlugin");
             return;
         }
 
-        let mut source = match source.canonicalize() {
-            Ok(path) => path,
-            Err(err) => {
-                error!("cannot canonicalize current executable path: {err}");
-                return;
+        let reader = HpakLayeredReader::default();
+        let archives = std::iter::once((&self.source, 0))
+            .chain(self.layers.iter().map(|(path, priority)| (path, *priority)));
+        let mut errors = Vec::new();
+
+        for (path, priority) in archives {
+            let path = base_path.join(path);
+
+            match self.open_archive(&path) {
+                Ok(archive) => reader.mount(archive, priority),
+                Err(error) => {
+                    error!("cannot mount hpak archive {}: {error}", path.display());
+                    errors.push(HpakMountError { path, error });
+                }
             }
+        }
+
+        app.insert_resource(reader.clone())
+            .insert_resource(HpakMountErrors(errors));
+
+        let source_id = match self.mode {
+            HistrionPackerMode::Autoload(source_id) => AssetSourceId::Name(source_id.into()),
+            HistrionPackerMode::ReplaceDefaultProcessed
+            | HistrionPackerMode::ReplaceDefault
+            | HistrionPackerMode::Hybrid(_) => AssetSourceId::Default,
         };
 
-        source.pop();
-        source.push(&self.source);
-
-        match self.mode {
-            HistrionPackerMode::Autoload(source_id) => {
-                app.register_asset_source(
-                    AssetSourceId::Name(source_id.into()),
-                    AssetSourceBuilder::new(|| {
-                        AssetSource::get_default_reader("assets".to_string())()
-                    })
-                    .with_processed_reader(move || {
-                        let source = source.clone();
-                        Box::new(HpakReader::new(&source).unwrap())
-                    }),
-                );
+        let builder = match &self.mode {
+            HistrionPackerMode::ReplaceDefault => AssetSourceBuilder::new(layered_reader(&reader))
+                .with_processed_reader(layered_reader(&reader)),
+            HistrionPackerMode::Hybrid(lookup_orders) => {
+                AssetSourceBuilder::new(hybrid_reader(&reader, "assets", lookup_orders))
+                    .with_processed_reader(hybrid_reader(
+                        &reader,
+                        "imported_assets/Default",
+                        lookup_orders,
+                    ))
             }
-            HistrionPackerMode::ReplaceDefaultProcessed => {
-                if app.is_plugin_added::<AssetPlugin>() {
-                    error!("plugin HistrionPackerPlugin must be added before plugin AssetPlugin");
-                    return;
-                }
+            _ => AssetSourceBuilder::new(AssetSource::get_default_reader("assets".to_string()))
+                .with_processed_reader(layered_reader(&reader)),
+        };
 
-                app.register_asset_source(
-                    AssetSourceId::Default,
-                    AssetSourceBuilder::new(|| {
-                        AssetSource::get_default_reader("assets".to_string())()
-                    })
-                    .with_processed_reader(move || {
-                        let source = source.clone();
-                        Box::new(HpakReader::new(&source).unwrap())
-                    }),
-                );
-            }
+        app.register_asset_source(source_id, builder);
+    }
+}
+
+impl HistrionPackerPlugin {
+    /// Open the archive at `path`, decrypting it with the plugin's key and checking
+    /// its signature if any.
+    fn open_archive(&self, path: &std::path::Path) -> Result<HpakReader> {
+        #[cfg(feature = "encryption")]
+        let reader = HpakReader::new_with_key_provider(path, |path| {
+            self.encryption_key.or_else(|| {
+                self.key_provider
+                    .as_ref()
+                    .and_then(|provider| provider(path))
+            })
+        })?;
+
+        #[cfg(not(feature = "encryption"))]
+        let reader = HpakReader::new(path)?;
+
+        #[cfg(feature = "signing")]
+        if let Some(public_key) = &self.public_key {
+            reader.verify_signature(public_key)?;
+            return Ok(reader.with_checksum_verification(true));
         }
+
+        Ok(reader)
+    }
+}
+
+/// Returns the directory of the current executable, archives paths are relative to it.
+fn executable_dir() -> std::io::Result<PathBuf> {
+    let mut path = std::env::current_exe()?.canonicalize()?;
+    path.pop();
+    Ok(path)
+}
+
+fn layered_reader(
+    reader: &HpakLayeredReader,
+) -> impl FnMut() -> Box<dyn ErasedAssetReader> + Send + Sync + 'static {
+    let reader = reader.clone();
+    move || Box::new(reader.clone())
+}
+
+fn hybrid_reader(
+    reader: &HpakLayeredReader,
+    path: &str,
+    lookup_orders: &[(String, LookupOrder)],
+) -> impl FnMut() -> Box<dyn ErasedAssetReader> + Send + Sync + 'static {
+    let reader = reader.clone();
+    let mut filesystem = AssetSource::get_default_reader(path.to_string());
+    let lookup_orders = lookup_orders.to_vec();
+
+    move || {
+        let hybrid = lookup_orders.iter().fold(
+            HpakHybridReader::new(reader.clone(), filesystem()),
+            |hybrid, (prefix, order)| hybrid.with_lookup_order(prefix, *order),
+        );
+
+        Box::new(hybrid)
     }
 }
 
@@ -191,3 +444,67 @@ pub mod writer {
 
     pub use format::writer::*;
 }
+
+#[cfg(all(test, feature = "writer"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_report_archives_failing_to_mount() {
+        let dir = format::temp_dir("plugin-errors");
+        let hotfix = format::write_archive(&dir, "hotfix.hpak", |writer| {
+            writer.add_bytes("a.txt", "()", "a");
+        });
+        let corrupted = dir.join("corrupted.hpak");
+        std::fs::write(&corrupted, "not an archive").unwrap();
+
+        let mut app = App::new();
+        app.add_plugins(
+            HistrionPackerPlugin::new(dir.join("missing.hpak").to_string_lossy())
+                .with_layer(hotfix.to_string_lossy(), 10)
+                .with_layer(corrupted.to_string_lossy(), 20)
+                .with_mode(HistrionPackerMode::Autoload("packed")),
+        );
+
+        let errors = app.world().resource::<HpakMountErrors>();
+        assert_eq!(2, errors.len());
+        assert_eq!(dir.join("missing.hpak"), errors[0].path);
+        assert!(matches!(errors[0].error, Error::Io(_)));
+        assert_eq!(corrupted, errors[1].path);
+
+        assert_eq!(1, app.world().resource::<HpakLayeredReader>().len());
+    }
+
+    #[cfg(feature = "signing")]
+    #[test]
+    fn it_reject_archives_not_signed_with_public_key() {
+        let dir = format::temp_dir("plugin-signature");
+        let signed = format::write_archive(&dir, "signed.hpak", |writer| {
+            writer
+                .with_signing_key(&[3; 32])
+                .add_bytes("a.txt", "()", "a");
+        });
+        let unsigned = format::write_archive(&dir, "unsigned.hpak", |writer| {
+            writer.add_bytes("a.txt", "()", "a");
+        });
+
+        let mut app = App::new();
+        app.add_plugins(
+            HistrionPackerPlugin::new(signed.to_string_lossy())
+                .with_layer(unsigned.to_string_lossy(), 10)
+                .with_mode(HistrionPackerMode::Autoload("packed"))
+                .with_public_key(
+                    ed25519_dalek::SigningKey::from_bytes(&[3; 32])
+                        .verifying_key()
+                        .to_bytes(),
+                ),
+        );
+
+        let errors = app.world().resource::<HpakMountErrors>();
+        assert_eq!(1, errors.len());
+        assert_eq!(unsigned, errors[0].path);
+        assert!(matches!(errors[0].error, Error::InvalidSignature));
+
+        assert_eq!(1, app.world().resource::<HpakLayeredReader>().len());
+    }
+}